@@ -0,0 +1,147 @@
+use crate::{try_absolute_path, try_canonical_parent, Unfolder};
+use anyhow::{bail, Result};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What exists at a hop in a chain of symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HopKind {
+    /// A symbolic link, which the chain continues through.
+    Symlink,
+    /// A file.
+    File,
+    /// A directory.
+    Dir,
+    /// Anything else, such as a FIFO or a device node.
+    Other,
+    /// Nothing, so the chain is broken.
+    Missing,
+}
+
+impl fmt::Display for HopKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HopKind::Symlink => "symlink",
+            HopKind::File => "file",
+            HopKind::Dir => "directory",
+            HopKind::Other => "other",
+            HopKind::Missing => "missing",
+        })
+    }
+}
+
+/// A single hop in a chain of symbolic links.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop {
+    /// Target of the previous symbolic link in the chain, exactly as read
+    /// from it.
+    pub link_target: PathBuf,
+    /// `link_target` joined with the parent directory of the previous
+    /// symbolic link in the chain.
+    pub path: PathBuf,
+    /// What exists at `path`.
+    pub kind: HopKind,
+}
+
+/// Description of how a symbolic link would be resolved, created with
+/// [`Unfolder::explain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// Absolute path of the symbolic link.
+    pub symlink: PathBuf,
+    /// Every hop in the chain of symbolic links, in order.
+    pub hops: Vec<Hop>,
+    /// Whether the last hop leads back to an earlier hop, in which case the
+    /// chain never ends.
+    pub looped: bool,
+    /// Index of the hop that unfolding would stop at, or `None` if the
+    /// symbolic link would be left untouched or could not be unfolded.
+    pub stop: Option<usize>,
+}
+
+/// Explain how `symlink` would be resolved by `unfolder`.
+pub(crate) fn try_explain(unfolder: &Unfolder, symlink: &Path) -> Result<Explanation> {
+    let symlink = try_absolute_path(symlink)?;
+    if !symlink.is_symlink() {
+        bail!("{:#?} is not a symlink.", symlink);
+    }
+    let (hops, looped) = try_walk_chain(&symlink)?;
+    let broken = hops.last().is_some_and(|hop| hop.kind == HopKind::Missing);
+    let stop = match (looped || broken, unfolder.follow_to_source) {
+        (true, _) => None,
+        (false, true) => Some(hops.len() - 1),
+        (false, false) => usize::from(unfolder.num_layers)
+            .min(hops.len())
+            .checked_sub(1),
+    };
+    Ok(Explanation {
+        symlink,
+        hops,
+        looped,
+        stop,
+    })
+}
+
+/// Follow the chain of symbolic links from `symlink` until reaching something
+/// other than a symbolic link, or a hop that was already visited. Returns
+/// every hop, and whether the chain loops.
+pub(crate) fn try_walk_chain(symlink: &Path) -> Result<(Vec<Hop>, bool)> {
+    let mut hops = Vec::new();
+    // paths are compared with their parent directories canonicalized, so
+    // different spellings of the same symbolic link are recognized.
+    let mut seen = vec![try_canonical_parent(symlink)?];
+    let mut path = symlink.to_path_buf();
+    while path.is_symlink() {
+        let link_target = path.read_link()?;
+        path = path.parent().unwrap().join(&link_target);
+        let metadata = path.symlink_metadata();
+        // a loop through a directory component of `path` is only noticed by
+        // the filesystem, which refuses to resolve it.
+        let looped = metadata.as_ref().is_err_and(is_loop);
+        let kind = match metadata {
+            Ok(meta) if meta.is_symlink() => HopKind::Symlink,
+            Ok(meta) if meta.is_file() => HopKind::File,
+            Ok(meta) if meta.is_dir() => HopKind::Dir,
+            Ok(_) => HopKind::Other,
+            Err(_) => HopKind::Missing,
+        };
+        hops.push(Hop {
+            link_target,
+            path: path.clone(),
+            kind,
+        });
+        if looped {
+            return Ok((hops, true));
+        }
+        let canonical = match try_canonical_parent(&path) {
+            Ok(canonical) => canonical,
+            Err(err) if err.downcast_ref::<io::Error>().is_some_and(is_loop) => {
+                return Ok((hops, true));
+            }
+            // the chain is broken rather than looping.
+            Err(_) => break,
+        };
+        if seen.contains(&canonical) {
+            return Ok((hops, true));
+        }
+        seen.push(canonical);
+    }
+    Ok((hops, false))
+}
+
+#[cfg(unix)]
+fn is_loop(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ELOOP)
+}
+
+#[cfg(windows)]
+fn is_loop(err: &io::Error) -> bool {
+    // ERROR_CANT_RESOLVE_FILENAME
+    err.raw_os_error() == Some(1921)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_loop(_err: &io::Error) -> bool {
+    false
+}
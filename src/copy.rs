@@ -0,0 +1,453 @@
+use crate::{normalize_path, relative_path, Unfolder};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, FileTimes, Metadata};
+use std::path::{Path, PathBuf};
+use symlink::symlink_auto;
+
+/// How symbolic links inside a directory are handled when it is deep copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Copy symbolic links. Relative targets are rewritten where needed, so
+    /// that they lead to the copies of paths inside the copied directory,
+    /// and to the same paths outside of it. Absolute targets are kept.
+    #[default]
+    Copy,
+    /// Copy the targets of symbolic links instead of the symbolic links.
+    Follow,
+    /// Leave symbolic links out of the copy.
+    Skip,
+}
+
+/// An attribute of a file or directory that can be preserved when copying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    /// Permission bits.
+    Mode,
+    /// Access and modification times.
+    Timestamps,
+    /// Owning user and group. If not permitted to change the owning user,
+    /// only the owning group is preserved, if possible.
+    Ownership,
+    /// Extended attributes.
+    Xattr,
+}
+
+/// Whether files are cloned with copy-on-write reflinks instead of copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reflink {
+    /// Clone files if the filesystem supports it, otherwise copy them.
+    #[default]
+    Auto,
+    /// Always clone files, failing if the filesystem does not support it.
+    Always,
+    /// Always copy files.
+    Never,
+}
+
+/// Copy the file `target` to `copy`, returning the number of bytes copied.
+pub(crate) fn try_copy_file(unfolder: &Unfolder, target: &Path, copy: &Path) -> Result<u64> {
+    let metadata = target
+        .metadata()
+        .context(format!("{:#?} is unreachable.", target))?;
+    let bytes = match unfolder.reflink {
+        Reflink::Auto if try_clone_file(target, &metadata, copy).is_ok() => metadata.len(),
+        Reflink::Always => {
+            try_clone_file(target, &metadata, copy).context(format!(
+                "Could not clone file {:#?} to {:#?}.",
+                target, copy
+            ))?;
+            metadata.len()
+        }
+        Reflink::Auto | Reflink::Never => std::fs::copy(target, copy)
+            .context(format!("Could not copy file {:#?} to {:#?}.", target, copy))?,
+    };
+    try_preserve(unfolder, target, &metadata, copy)?;
+    Ok(bytes)
+}
+
+/// Clone the file `target` to the new file `copy` with a reflink, sharing
+/// its data until either file is modified.
+#[cfg(target_os = "linux")]
+fn try_clone_file(target: &Path, metadata: &Metadata, copy: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = File::open(target)?;
+    let dest = File::options().write(true).create_new(true).open(copy)?;
+    // SAFETY: both file descriptors remain open for the duration of the call.
+    let result = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) };
+    if result == -1 {
+        let err = std::io::Error::last_os_error();
+        drop(dest);
+        let _ = std::fs::remove_file(copy);
+        return Err(err);
+    }
+    // match std::fs::copy, which always copies the permissions.
+    dest.set_permissions(metadata.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn try_clone_file(_target: &Path, _metadata: &Metadata, _copy: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+/// Whether `metadata` describes something other than a file, directory or
+/// symbolic link, such as a FIFO, socket or device node.
+pub(crate) fn is_special(metadata: &Metadata) -> bool {
+    let file_type = metadata.file_type();
+    !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink()
+}
+
+/// Whether `metadata` describes a socket, which cannot be recreated.
+#[cfg(unix)]
+fn is_socket(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    metadata.file_type().is_socket()
+}
+
+#[cfg(not(unix))]
+fn is_socket(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// Check that the special file `target` can be recreated with
+/// [`try_copy_special`].
+#[cfg(unix)]
+pub(crate) fn validate_special(target: &Path, metadata: &Metadata) -> Result<()> {
+    if is_socket(metadata) {
+        bail!(
+            "Could not recreate {:#?}: sockets cannot be recreated.",
+            target
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn validate_special(target: &Path, _metadata: &Metadata) -> Result<()> {
+    bail!(
+        "Could not recreate {:#?}: special files can only be recreated on Unix.",
+        target
+    )
+}
+
+/// Recreate the FIFO or device node `target` at `copy`, with the same device
+/// numbers.
+#[cfg(unix)]
+pub(crate) fn try_copy_special(unfolder: &Unfolder, target: &Path, copy: &Path) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let metadata = target
+        .metadata()
+        .context(format!("{:#?} is unreachable.", target))?;
+    validate_special(target, &metadata)?;
+    let file_type = metadata.file_type();
+    let copy_c = std::ffi::CString::new(copy.as_os_str().as_bytes())?;
+    let mode = (metadata.mode() & 0o7777) as libc::mode_t;
+    // SAFETY: the path is a valid NUL-terminated string that outlives the
+    // calls, and neither mkfifo nor mknod retain it.
+    let result = match file_type.is_fifo() {
+        true => unsafe { libc::mkfifo(copy_c.as_ptr(), mode) },
+        false => {
+            let kind = match file_type.is_char_device() {
+                true => libc::S_IFCHR,
+                false => libc::S_IFBLK,
+            };
+            unsafe { libc::mknod(copy_c.as_ptr(), kind | mode, metadata.rdev() as libc::dev_t) }
+        }
+    };
+    if result == -1 {
+        let err = std::io::Error::last_os_error();
+        if !file_type.is_fifo() && err.kind() == std::io::ErrorKind::PermissionDenied {
+            bail!(
+                "Could not recreate device node {:#?}: only root can create device nodes.",
+                target
+            );
+        }
+        return Err(err).context(format!("Could not recreate {:#?} at {:#?}.", target, copy));
+    }
+    try_preserve(unfolder, target, &metadata, copy)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn try_copy_special(_unfolder: &Unfolder, target: &Path, _copy: &Path) -> Result<()> {
+    let metadata = target
+        .metadata()
+        .context(format!("{:#?} is unreachable.", target))?;
+    validate_special(target, &metadata)
+}
+
+/// Apply the attributes of `original` that `unfolder` preserves to `copy`.
+///
+/// `metadata` must be read from `original` before it is copied, since
+/// copying can update its access time.
+pub(crate) fn try_preserve(
+    unfolder: &Unfolder,
+    original: &Path,
+    metadata: &Metadata,
+    copy: &Path,
+) -> Result<()> {
+    let preserve = |attribute| unfolder.preserve.contains(&attribute);
+    if preserve(Attribute::Ownership) {
+        try_preserve_ownership(metadata, copy)
+            .context(format!("Could not preserve ownership of {:#?}.", copy))?;
+    }
+    if preserve(Attribute::Xattr) {
+        try_preserve_xattrs(original, copy).context(format!(
+            "Could not preserve extended attributes of {:#?}.",
+            copy
+        ))?;
+    }
+    // timestamps are set before the mode, since the new mode may not allow
+    // opening the copy.
+    if preserve(Attribute::Timestamps) {
+        try_preserve_timestamps(metadata, copy)
+            .context(format!("Could not preserve timestamps of {:#?}.", copy))?;
+    }
+    if preserve(Attribute::Mode) {
+        std::fs::set_permissions(copy, metadata.permissions())
+            .context(format!("Could not preserve mode of {:#?}.", copy))?;
+    }
+    Ok(())
+}
+
+fn try_preserve_timestamps(metadata: &Metadata, copy: &Path) -> Result<()> {
+    // opening a FIFO blocks until its other end is opened, and opening a
+    // device node can have side effects, so they are never opened.
+    if is_special(metadata) {
+        return try_set_special_times(metadata, copy);
+    }
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    Ok(File::open(copy)?.set_times(times)?)
+}
+
+#[cfg(unix)]
+fn try_set_special_times(metadata: &Metadata, copy: &Path) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let copy_c = std::ffi::CString::new(copy.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: metadata.atime() as libc::time_t,
+            tv_nsec: metadata.atime_nsec() as _,
+        },
+        libc::timespec {
+            tv_sec: metadata.mtime() as libc::time_t,
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    ];
+    // SAFETY: the path is a valid NUL-terminated string and `times` holds
+    // the two timestamps utimensat reads, both outliving the call.
+    let result = unsafe { libc::utimensat(libc::AT_FDCWD, copy_c.as_ptr(), times.as_ptr(), 0) };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn try_set_special_times(_metadata: &Metadata, _copy: &Path) -> Result<()> {
+    bail!("Special files can only be recreated on Unix.")
+}
+
+#[cfg(unix)]
+fn try_preserve_ownership(metadata: &Metadata, copy: &Path) -> Result<()> {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    match chown(copy, Some(metadata.uid()), Some(metadata.gid())) {
+        // like cp, only unprivileged users can't change the owning user,
+        // so fall back to preserving the group and otherwise give up.
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            let _ = chown(copy, None, Some(metadata.gid()));
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+#[cfg(not(unix))]
+fn try_preserve_ownership(_metadata: &Metadata, _copy: &Path) -> Result<()> {
+    bail!("Ownership can only be preserved on Unix.")
+}
+
+#[cfg(unix)]
+fn try_preserve_xattrs(original: &Path, copy: &Path) -> Result<()> {
+    for name in xattr::list(original)? {
+        if crate::mark::is_mark(&name) {
+            continue;
+        }
+        if let Some(value) = xattr::get(original, &name)? {
+            xattr::set(copy, &name, &value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn try_preserve_xattrs(_original: &Path, _copy: &Path) -> Result<()> {
+    bail!("Extended attributes can only be preserved on Unix.")
+}
+
+/// Recursively copy the contents of `target_dir` into the existing directory
+/// `dir`, which ends up at `location`, returning the number of bytes copied.
+/// Sockets, which cannot be recreated, are left out and added to `skipped`.
+pub(crate) fn try_copy_dir_contents(
+    unfolder: &Unfolder,
+    dir: &Path,
+    location: &Path,
+    target_dir: &Path,
+    skipped: &mut Vec<PathBuf>,
+) -> Result<u64> {
+    let mut ancestors = vec![target_dir
+        .canonicalize()
+        .context(format!("{:#?} is unreachable.", target_dir))?];
+    let staged = dir
+        .canonicalize()
+        .context(format!("{:#?} is unreachable.", dir))?;
+    try_copy_children(
+        unfolder,
+        Some((dir, location)),
+        target_dir,
+        Some(&staged),
+        &mut ancestors,
+        skipped,
+    )
+}
+
+/// Find the number of bytes [`try_copy_dir_contents`] would copy, without
+/// modifying the filesystem, adding the sockets it would leave out to
+/// `skipped`.
+pub(crate) fn try_measure_dir(
+    unfolder: &Unfolder,
+    target_dir: &Path,
+    skipped: &mut Vec<PathBuf>,
+) -> Result<u64> {
+    let mut ancestors = vec![target_dir
+        .canonicalize()
+        .context(format!("{:#?} is unreachable.", target_dir))?];
+    try_copy_children(unfolder, None, target_dir, None, &mut ancestors, skipped)
+}
+
+/// Copy the children of `target_dir` into the directory `dir.0`, which ends
+/// up at `dir.1`. If `dir` is `None`, the children are only visited to count
+/// the bytes that would be copied. `staged` is the canonical path of the
+/// whole copy being built, which is never copied into itself.
+fn try_copy_children(
+    unfolder: &Unfolder,
+    dir: Option<(&Path, &Path)>,
+    target_dir: &Path,
+    staged: Option<&Path>,
+    ancestors: &mut Vec<PathBuf>,
+    skipped: &mut Vec<PathBuf>,
+) -> Result<u64> {
+    let children = target_dir
+        .read_dir()
+        .context(format!("Could not read contents of {:#?}", target_dir))?;
+    let mut bytes = 0;
+    for child in children {
+        let target = &child?.path();
+        let file_name = target.file_name().unwrap();
+        // the staging directory is built next to the symbolic link, so it is
+        // listed here when the target contains the link itself.
+        if staged.is_some_and(|staged| ancestors.last().unwrap().join(file_name) == staged) {
+            continue;
+        }
+        let copy = &dir.map(|(dir, location)| (dir.join(file_name), location.join(file_name)));
+        if target.is_symlink() {
+            match unfolder.symlink_policy {
+                SymlinkPolicy::Copy => {
+                    if let (Some((copy, _)), Some((_, location))) = (copy, dir) {
+                        let link_target = relocate_link(target.read_link()?, ancestors, location);
+                        symlink_auto(link_target, copy).context(format!(
+                            "Could not copy symlink {:#?} to {:#?}",
+                            target, copy
+                        ))?;
+                    }
+                    continue;
+                }
+                SymlinkPolicy::Skip => continue,
+                SymlinkPolicy::Follow => {
+                    crate::validate_symlink(target)?;
+                    unfolder.try_check_chain_within(target)?;
+                }
+            }
+        }
+
+        if target.is_file() {
+            bytes += match copy {
+                Some((copy, _)) => try_copy_file(unfolder, target, copy)?,
+                None => target.metadata()?.len(),
+            };
+        } else if target.is_dir() {
+            // following symlinks can lead back to a directory that is
+            // already being copied, which would never finish.
+            let canonical = target.canonicalize()?;
+            if ancestors.contains(&canonical) {
+                bail!("Could not copy {:#?}: it contains itself.", target);
+            }
+            let metadata = target.metadata()?;
+            if let Some((copy, _)) = copy {
+                std::fs::create_dir(copy)
+                    .context(format!("Could not create directory at {:#?}.", copy))?;
+            }
+            ancestors.push(canonical);
+            let dir = copy
+                .as_ref()
+                .map(|(copy, location)| (copy.as_path(), location.as_path()));
+            bytes += try_copy_children(unfolder, dir, target, staged, ancestors, skipped)?;
+            ancestors.pop();
+            if let Some((copy, _)) = copy {
+                try_preserve(unfolder, target, &metadata, copy)?;
+            }
+        } else if unfolder.skip_special {
+            continue;
+        } else if is_socket(&target.metadata()?) {
+            // a single socket should not abort copying everything around it.
+            skipped.push(target.clone());
+        } else {
+            match copy {
+                Some((copy, _)) => try_copy_special(unfolder, target, copy)?,
+                None => validate_special(target, &target.metadata()?)?,
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Rewrite the target `link_target` of a symbolic link inside
+/// `ancestors.last()`, which is being copied into the directory that ends up
+/// at `location`, as described in [`SymlinkPolicy::Copy`].
+///
+/// The first of `ancestors` is the directory being deep copied, and the last
+/// is the directory holding the symbolic link, both canonicalized.
+fn relocate_link(link_target: PathBuf, ancestors: &[PathBuf], location: &Path) -> PathBuf {
+    let (root, dir) = (&ancestors[0], ancestors.last().unwrap());
+    if link_target.is_absolute() {
+        return link_target;
+    }
+    let destination = normalize_path(&dir.join(&link_target));
+    let relocated = match destination.starts_with(root) {
+        true => relative_path(dir, &destination),
+        false => relative_path(location, &destination),
+    };
+    let relocated = match relocated.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => relocated,
+    };
+    // targets that still lead to the right place are kept as written.
+    let leads_to = |link_target: &Path| normalize_path(&location.join(link_target));
+    if leads_to(&link_target) == leads_to(&relocated) {
+        link_target
+    } else {
+        relocated
+    }
+}
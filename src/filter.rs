@@ -0,0 +1,45 @@
+use crate::{try_canonical_parent, try_find_target, Unfolder};
+use anyhow::Result;
+use glob::Pattern;
+use std::path::Path;
+
+/// Glob patterns selecting which symbolic links are unfolded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    pub(crate) include: Vec<Pattern>,
+    pub(crate) exclude: Vec<Pattern>,
+    pub(crate) target_include: Vec<Pattern>,
+    pub(crate) target_exclude: Vec<Pattern>,
+}
+
+/// Check whether `path`, the symbolic link as given, passes the link-side
+/// filters of `unfolder`.
+pub(crate) fn is_path_selected(unfolder: &Unfolder, path: &Path) -> bool {
+    let filter = &unfolder.filter;
+    is_selected(&filter.include, &filter.exclude, path)
+}
+
+/// Check whether `symlink` passes the target-side filters of `unfolder`,
+/// which are matched against the absolute path of the target it would be
+/// unfolded to. Its parent directory is canonicalized first, so that relative
+/// symbolic links with `..` are matched by where they lead.
+pub(crate) fn try_is_target_selected(unfolder: &Unfolder, symlink: &Path) -> Result<bool> {
+    let filter = &unfolder.filter;
+    if filter.target_include.is_empty() && filter.target_exclude.is_empty() {
+        return Ok(true);
+    }
+    let target = try_find_target(symlink, unfolder.num_layers, unfolder.follow_to_source)?;
+    let target = try_canonical_parent(&target)?;
+    Ok(is_selected(
+        &filter.target_include,
+        &filter.target_exclude,
+        &target,
+    ))
+}
+
+/// A path is selected if it matches any of `include`, or `include` is empty,
+/// and it matches none of `exclude`.
+fn is_selected(include: &[Pattern], exclude: &[Pattern], path: &Path) -> bool {
+    (include.is_empty() || include.iter().any(|pattern| pattern.matches_path(path)))
+        && !exclude.iter().any(|pattern| pattern.matches_path(path))
+}
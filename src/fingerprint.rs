@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::Path;
+
+/// 64-bit FNV-1a, which is stable across platforms and Rust versions, unlike
+/// the hashers in the standard library.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Compute a fingerprint of whatever exists at `path`, without following
+/// symbolic links.
+///
+/// The fingerprint covers the contents of files, the targets of symbolic
+/// links, the types and device numbers of special files, and the names and
+/// fingerprints of everything inside directories, so it changes if anything
+/// at `path` is modified.
+pub(crate) fn try_fingerprint(path: &Path) -> Result<String> {
+    let mut hasher = Fnv1a::default();
+    try_hash(path, &mut hasher)?;
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Compute a fingerprint of the directory at `path` as it is unfolded into a
+/// directory of symbolic links with `dir_depth` levels of real directories.
+///
+/// Only the names of entries are covered, along with which of them are
+/// directories within the first `dir_depth` levels, since everything else is
+/// reached through the symbolic links and never copied.
+pub(crate) fn try_fingerprint_levels(path: &Path, dir_depth: u8) -> Result<String> {
+    let mut hasher = Fnv1a::default();
+    try_hash_levels(path, &mut hasher, dir_depth)?;
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+fn try_hash_levels(path: &Path, hasher: &mut Fnv1a, dir_depth: u8) -> Result<()> {
+    let mut children = path
+        .read_dir()
+        .context(format!("Could not read contents of {:#?}", path))?
+        .map(|child| {
+            let child = child?;
+            Ok((child.path(), child.file_type()?.is_dir()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    children.sort();
+    hasher.write(b"d");
+    for (child, is_dir) in children {
+        hasher.write(child.file_name().unwrap().as_encoded_bytes());
+        hasher.write_u8(0);
+        if dir_depth > 1 && is_dir {
+            try_hash_levels(&child, hasher, dir_depth - 1)?;
+        } else {
+            hasher.write(b"l");
+        }
+    }
+    hasher.write(b"e");
+    Ok(())
+}
+
+fn try_hash(path: &Path, hasher: &mut Fnv1a) -> Result<()> {
+    let metadata = path
+        .symlink_metadata()
+        .context(format!("{:#?} is unreachable.", path))?;
+    if metadata.is_symlink() {
+        hasher.write(b"l");
+        hasher.write(path.read_link()?.as_os_str().as_encoded_bytes());
+    } else if metadata.is_dir() {
+        hasher.write(b"d");
+        let mut children = path
+            .read_dir()
+            .context(format!("Could not read contents of {:#?}", path))?
+            .map(|child| Ok(child?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            hasher.write(child.file_name().unwrap().as_encoded_bytes());
+            hasher.write_u8(0);
+            try_hash(&child, hasher)?;
+        }
+        hasher.write(b"e");
+    } else if crate::copy::is_special(&metadata) {
+        // special files are never opened, since opening a FIFO blocks.
+        hasher.write(b"s");
+        hash_special(&metadata, hasher);
+    } else {
+        hasher.write(b"f");
+        hasher.write(&metadata.len().to_le_bytes());
+        let mut file = File::open(path).context(format!("Could not read {:#?}.", path))?;
+        let mut buf = [0; 8192];
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            hasher.write(&buf[..len]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn hash_special(metadata: &std::fs::Metadata, hasher: &mut Fnv1a) {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_fifo() {
+        b"p"
+    } else if file_type.is_char_device() {
+        b"c"
+    } else if file_type.is_block_device() {
+        b"b"
+    } else {
+        b"s"
+    };
+    hasher.write(kind);
+    hasher.write(&metadata.rdev().to_le_bytes());
+}
+
+#[cfg(not(unix))]
+fn hash_special(_metadata: &std::fs::Metadata, _hasher: &mut Fnv1a) {}
@@ -0,0 +1,104 @@
+use crate::{relative_path, try_absolute_path, try_canonical_parent, try_relink};
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Description of a file that was folded into a symbolic link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folded {
+    /// Absolute path of the file that was replaced with a symbolic link.
+    pub file: PathBuf,
+    /// Target of the new symbolic link, exactly as written to it.
+    pub link_target: PathBuf,
+}
+
+/// Folds files into symbolic links to identical files, the inverse of
+/// unfolding.
+///
+/// A `Folder` is created with [`Folder::new`] and configured using its
+/// builder methods. By default, symbolic links are created with absolute
+/// targets.
+#[derive(Debug, Clone, Default)]
+pub struct Folder {
+    relative: bool,
+}
+
+impl Folder {
+    /// Create a `Folder` with the default options.
+    pub fn new() -> Folder {
+        Folder::default()
+    }
+
+    /// Create symbolic links with targets relative to the directory of the
+    /// folded file, instead of absolute targets.
+    pub fn relative(mut self, relative: bool) -> Folder {
+        self.relative = relative;
+        self
+    }
+
+    /// Replace `file` with a symbolic link to `reference`.
+    ///
+    /// Both must be files with byte-identical contents. The symbolic link is
+    /// built at a temporary path next to `file`, and only swapped with it once
+    /// complete, so `file` is left untouched if an error occurs.
+    pub fn fold<P: AsRef<Path>, Q: AsRef<Path>>(&self, file: P, reference: Q) -> Result<Folded> {
+        let file = &try_absolute_path(file.as_ref())?;
+        let reference = reference.as_ref();
+        if file.is_symlink() || !file.is_file() {
+            bail!("{:#?} is not a file.", file);
+        } else if !reference.is_file() {
+            bail!("{:#?} is not a file.", reference);
+        }
+
+        let dir = file
+            .parent()
+            .context(format!("{:#?} does not have a parent directory.", file))?
+            .canonicalize()
+            .context(format!("{:#?} is unreachable.", file))?;
+        let reference = &try_canonical_parent(reference)?;
+        if dir.join(file.file_name().unwrap()) == reference.canonicalize()? {
+            bail!("Could not fold {:#?} into itself.", file);
+        }
+        if !try_same_contents(file, reference)? {
+            bail!(
+                "Could not fold {:#?}: its contents differ from {:#?}.",
+                file,
+                reference
+            );
+        }
+
+        let link_target = match self.relative {
+            true => relative_path(&dir, reference),
+            false => reference.clone(),
+        };
+        try_relink(file, &link_target).context(format!("Could not fold {:#?}.", file))?;
+        Ok(Folded {
+            file: file.into(),
+            link_target,
+        })
+    }
+}
+
+/// Check whether the files `file` and `reference` have identical contents.
+fn try_same_contents(file: &Path, reference: &Path) -> Result<bool> {
+    let mut file = File::open(file).context(format!("Could not read {:#?}.", file))?;
+    let mut reference =
+        File::open(reference).context(format!("Could not read {:#?}.", reference))?;
+    if file.metadata()?.len() != reference.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut file_buf = [0; 8192];
+    let mut reference_buf = [0; 8192];
+    loop {
+        let len = file.read(&mut file_buf)?;
+        if len == 0 {
+            return Ok(true);
+        }
+        reference.read_exact(&mut reference_buf[..len])?;
+        if file_buf[..len] != reference_buf[..len] {
+            return Ok(false);
+        }
+    }
+}
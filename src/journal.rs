@@ -0,0 +1,106 @@
+use crate::fingerprint::try_fingerprint;
+use crate::{try_relink, UnfoldKind, Unfolded};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// A record of a single unfolded symbolic link, as written to a [`Journal`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Absolute path of the symbolic link that was unfolded.
+    pub symlink: PathBuf,
+    /// Original target of the symbolic link, exactly as read from it.
+    pub link_target: PathBuf,
+    /// Path of the target that the symbolic link was unfolded to.
+    pub target: PathBuf,
+    /// What the symbolic link was replaced with.
+    pub kind: UnfoldKind,
+    /// Fingerprint of what the symbolic link was replaced with, used to
+    /// detect changes made since it was unfolded.
+    pub fingerprint: String,
+}
+
+impl JournalEntry {
+    /// Restore the original symbolic link.
+    ///
+    /// Fails if whatever exists at the path of the symbolic link has changed
+    /// since it was unfolded, unless `force` is set. Returns `false` if the
+    /// symbolic link was already restored, in which case nothing is done.
+    pub fn undo(&self, force: bool) -> Result<bool> {
+        if self
+            .symlink
+            .read_link()
+            .is_ok_and(|link_target| link_target == self.link_target)
+        {
+            return Ok(false);
+        }
+        if !force && try_fingerprint(&self.symlink)? != self.fingerprint {
+            bail!("{:#?} has changed since it was unfolded.", self.symlink);
+        }
+        try_relink(&self.symlink, &self.link_target)
+            .context(format!("Could not restore {:#?}.", self.symlink))?;
+        Ok(true)
+    }
+}
+
+/// A file recording every unfolded symbolic link, so that they can be
+/// restored later.
+///
+/// The journal is written as JSON Lines, with one [`JournalEntry`] per line.
+/// Entries are appended, so a journal can span many runs.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal at `path` for appending, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Journal> {
+        let path = path.as_ref();
+        let file = File::options()
+            .append(true)
+            .create(true)
+            .open(path)
+            .context(format!("Could not open journal {:#?}.", path))?;
+        Ok(Journal { file })
+    }
+
+    /// Append an entry for a symbolic link that was unfolded.
+    pub fn record(&mut self, unfolded: &Unfolded) -> Result<()> {
+        let entry = JournalEntry {
+            symlink: unfolded.symlink.clone(),
+            link_target: unfolded.link_target.clone(),
+            target: unfolded.target.clone(),
+            kind: unfolded.kind,
+            fingerprint: try_fingerprint(&unfolded.symlink)?,
+        };
+        let mut line = serde_json::to_string(&entry)
+            .context(format!("Could not record {:#?}.", unfolded.symlink))?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .context(format!("Could not record {:#?}.", unfolded.symlink))
+    }
+
+    /// Read every entry of the journal at `path`, in the order they were
+    /// recorded.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<JournalEntry>> {
+        let path = path.as_ref();
+        let file = File::open(path).context(format!("Could not open journal {:#?}.", path))?;
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context(format!("Could not read journal {:#?}.", path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str(&line).context(format!(
+                "Line {} of journal {:#?} is invalid.",
+                i + 1,
+                path
+            ))?);
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,1094 @@
+//! Replace symbolic links with their targets.
+//!
+//! This crate provides the library behind the `unfold` command line utility.
+//! Unfolding is configured through an [`Unfolder`], which can then be used to
+//! unfold any number of symbolic links:
+//!
+//! ```no_run
+//! use unfold_symlinks::{UnfoldKind, Unfolder, Unfolding};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let unfolder = Unfolder::new().follow_to_source(true);
+//! if let Unfolding::Unfolded(unfolded) = unfolder.unfold("assets/logo.png")? {
+//!     assert_eq!(unfolded.kind, UnfoldKind::File);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod chain;
+mod copy;
+mod filter;
+mod fingerprint;
+mod fold;
+mod journal;
+mod mark;
+mod stage;
+mod walk;
+
+pub use chain::{Explanation, Hop, HopKind};
+pub use copy::{Attribute, Reflink, SymlinkPolicy};
+pub use fold::{Folded, Folder};
+pub use glob::Pattern;
+pub use journal::{Journal, JournalEntry};
+pub use mark::{read_mark, refold, Mark};
+pub use walk::find_symlinks;
+
+use anyhow::{bail, Context, Result};
+use filter::Filter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+use symlink::symlink_auto;
+
+/// What a symbolic link was replaced with when it was unfolded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnfoldKind {
+    /// The target is a symbolic link, so the symbolic link was replaced with
+    /// a copy of it.
+    Symlink,
+    /// The target is a file, so the symbolic link was replaced with a copy
+    /// of it.
+    File,
+    /// The target is a file, so the symbolic link was replaced with a hard
+    /// link to it.
+    HardLink,
+    /// The target is a directory, so the symbolic link was replaced with a
+    /// directory containing symbolic links to the contents of the target.
+    Dir,
+    /// The target is a directory, so the symbolic link was replaced with a
+    /// recursive copy of it.
+    DeepDir,
+    /// The target is a FIFO or a device node, so the symbolic link was
+    /// replaced with a new one of the same type and device numbers.
+    Special,
+}
+
+impl fmt::Display for UnfoldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnfoldKind::Symlink => "symlink copy",
+            UnfoldKind::File => "file copy",
+            UnfoldKind::HardLink => "hard link",
+            UnfoldKind::Dir => "directory population",
+            UnfoldKind::DeepDir => "directory copy",
+            UnfoldKind::Special => "special file",
+        })
+    }
+}
+
+/// Outcome of refreshing a marked path with [`Unfolder::refresh`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshStatus {
+    /// The target has not changed, so nothing was done.
+    UpToDate,
+    /// The path was rebuilt from its changed target.
+    Updated,
+    /// The target has changed, but so has the path, so nothing was done to
+    /// avoid losing its local modifications.
+    Modified,
+}
+
+/// How the targets of symbolic links created inside unfolded directories are
+/// written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkStyle {
+    /// Write absolute targets.
+    #[default]
+    Absolute,
+    /// Write targets relative to the location of each symbolic link.
+    Relative,
+    /// Write relative targets if the unfolded symbolic link had a relative
+    /// target, and absolute targets otherwise.
+    Preserve,
+}
+
+/// How broken symbolic links are handled when unfolding.
+///
+/// A symbolic link is broken if its chain of symbolic links leads to a path
+/// that does not exist, at any point in the chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BrokenPolicy {
+    /// Fail with an error.
+    #[default]
+    Error,
+    /// Leave them untouched, to be reported with a warning.
+    Skip,
+    /// Remove them.
+    Remove,
+    /// Leave them untouched.
+    Keep,
+}
+
+/// Description of a symbolic link that was unfolded, or that would be
+/// unfolded when planning with [`Unfolder::plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unfolded {
+    /// Absolute path of the symbolic link that was unfolded.
+    pub symlink: PathBuf,
+    /// Original target of the symbolic link, exactly as read from it.
+    pub link_target: PathBuf,
+    /// Path of the target that the symbolic link was unfolded to.
+    pub target: PathBuf,
+    /// Every target followed from the symbolic link in order, each being the
+    /// previous path's target joined with its parent directory.
+    pub chain: Vec<PathBuf>,
+    /// What the symbolic link was replaced with.
+    pub kind: UnfoldKind,
+    /// Number of bytes of file contents copied while unfolding.
+    pub bytes: u64,
+    /// Sockets left out of a directory copied by [`Unfolder::deep`], since
+    /// they cannot be recreated.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Why a symbolic link was left untouched by [`Unfolder::unfold`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    /// Zero layers are followed, see [`Unfolder::num_layers`].
+    NoLayers,
+    /// The symbolic link or its target is filtered out by
+    /// [`Unfolder::include`], [`Unfolder::exclude`],
+    /// [`Unfolder::target_include`] or [`Unfolder::target_exclude`].
+    Filtered,
+    /// The symbolic link is broken, and was handled according to the given
+    /// [`BrokenPolicy`], which may have removed it.
+    Broken(BrokenPolicy),
+    /// The symbolic link targets a special file, and
+    /// [`Unfolder::skip_special`] is set.
+    Special,
+}
+
+/// What happened to a symbolic link given to [`Unfolder::unfold`], or what
+/// would happen to it when planning with [`Unfolder::plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unfolding {
+    /// The symbolic link was unfolded.
+    Unfolded(Unfolded),
+    /// The symbolic link was skipped.
+    Skipped(Skip),
+}
+
+impl Unfolding {
+    /// Return the unfolded symbolic link, if it was not skipped.
+    pub fn unfolded(self) -> Option<Unfolded> {
+        match self {
+            Unfolding::Unfolded(unfolded) => Some(unfolded),
+            Unfolding::Skipped(_) => None,
+        }
+    }
+}
+
+impl Unfolded {
+    /// Revert the unfold, restoring the original symbolic link.
+    ///
+    /// Whatever currently exists at the path of the symbolic link is removed,
+    /// including any changes made to it since it was unfolded.
+    pub fn revert(&self) -> Result<()> {
+        try_relink(&self.symlink, &self.link_target)
+            .context(format!("Could not revert {:#?}.", self.symlink))
+    }
+}
+
+/// Unfolds symbolic links to their targets.
+///
+/// An `Unfolder` is created with [`Unfolder::new`] and configured using its
+/// builder methods. By default, symbolic links are unfolded to their
+/// immediate targets. Nothing is ever written to stdout or stderr, since
+/// everything that happened is returned instead.
+#[derive(Debug, Clone)]
+pub struct Unfolder {
+    num_layers: u8,
+    follow_to_source: bool,
+    deep: bool,
+    dir_depth: u8,
+    symlink_policy: SymlinkPolicy,
+    broken: BrokenPolicy,
+    link_style: LinkStyle,
+    preserve: Vec<Attribute>,
+    reflink: Reflink,
+    hardlink: bool,
+    skip_special: bool,
+    mark: bool,
+    filter: Filter,
+    within: Option<PathBuf>,
+}
+
+impl Default for Unfolder {
+    fn default() -> Unfolder {
+        Unfolder {
+            num_layers: 1,
+            follow_to_source: false,
+            deep: false,
+            dir_depth: 1,
+            symlink_policy: SymlinkPolicy::default(),
+            broken: BrokenPolicy::default(),
+            link_style: LinkStyle::default(),
+            preserve: Vec::new(),
+            reflink: Reflink::default(),
+            hardlink: false,
+            skip_special: false,
+            mark: false,
+            filter: Filter::default(),
+            within: None,
+        }
+    }
+}
+
+impl Unfolder {
+    /// Create an `Unfolder` with the default options.
+    pub fn new() -> Unfolder {
+        Unfolder::default()
+    }
+
+    /// Follow up to `num_layers` symbolic links in a chain of symbolic links.
+    ///
+    /// Defaults to 1. If set to 0, symbolic links are left untouched.
+    /// Ignored if [`Unfolder::follow_to_source`] is set.
+    pub fn num_layers(mut self, num_layers: u8) -> Unfolder {
+        self.num_layers = num_layers;
+        self
+    }
+
+    /// Follow all symbolic links in a chain of symbolic links to the source.
+    pub fn follow_to_source(mut self, follow_to_source: bool) -> Unfolder {
+        self.follow_to_source = follow_to_source;
+        self
+    }
+
+    /// Create real directories for the first `dir_depth` levels of target
+    /// directories, with symbolic links to the contents of the target below
+    /// that depth.
+    ///
+    /// Defaults to 1, which only creates the unfolded directory itself, with
+    /// symbolic links to every entry of the target directory. Symbolic links
+    /// inside the target directory are never descended into. Ignored if
+    /// [`Unfolder::deep`] is set.
+    pub fn dir_depth(mut self, dir_depth: u8) -> Unfolder {
+        self.dir_depth = dir_depth;
+        self
+    }
+
+    /// How to handle broken symbolic links.
+    ///
+    /// Defaults to [`BrokenPolicy::Error`]. Only the link-side filters,
+    /// [`Unfolder::include`] and [`Unfolder::exclude`], apply to broken
+    /// symbolic links, since they have no target to match against.
+    /// [`BrokenPolicy::Remove`] removes the given symbolic link itself, not
+    /// the dangling symbolic link it leads to, and removed symbolic links
+    /// cannot be reverted.
+    pub fn broken(mut self, broken: BrokenPolicy) -> Unfolder {
+        self.broken = broken;
+        self
+    }
+
+    /// Replace symbolic links to directories with a recursive copy of the
+    /// target directory, instead of a directory of symbolic links.
+    pub fn deep(mut self, deep: bool) -> Unfolder {
+        self.deep = deep;
+        self
+    }
+
+    /// How to handle symbolic links inside directories copied by
+    /// [`Unfolder::deep`].
+    ///
+    /// Defaults to [`SymlinkPolicy::Copy`].
+    pub fn symlink_policy(mut self, symlink_policy: SymlinkPolicy) -> Unfolder {
+        self.symlink_policy = symlink_policy;
+        self
+    }
+
+    /// How to write the targets of the symbolic links created when a symbolic
+    /// link to a directory is unfolded without [`Unfolder::deep`].
+    ///
+    /// Relative targets keep working when the unfolded directory and its
+    /// target are moved or mounted elsewhere together. Defaults to
+    /// [`LinkStyle::Absolute`].
+    pub fn link_style(mut self, link_style: LinkStyle) -> Unfolder {
+        self.link_style = link_style;
+        self
+    }
+
+    /// Preserve the given attributes of targets on the files and directories
+    /// created when unfolding.
+    ///
+    /// Defaults to preserving nothing, although copies of files always keep
+    /// the permissions of their targets.
+    pub fn preserve<I: IntoIterator<Item = Attribute>>(mut self, attributes: I) -> Unfolder {
+        self.preserve = attributes.into_iter().collect();
+        self
+    }
+
+    /// Whether to clone files with copy-on-write reflinks instead of copying
+    /// them, on filesystems that support it, such as Btrfs and XFS.
+    ///
+    /// Defaults to [`Reflink::Auto`].
+    pub fn reflink(mut self, reflink: Reflink) -> Unfolder {
+        self.reflink = reflink;
+        self
+    }
+
+    /// Replace symbolic links to files with hard links to their targets,
+    /// instead of copies.
+    ///
+    /// Hard links cannot span filesystems, so unfolding fails if the target
+    /// is on a different filesystem than the symbolic link.
+    pub fn hardlink(mut self, hardlink: bool) -> Unfolder {
+        self.hardlink = hardlink;
+        self
+    }
+
+    /// Leave symbolic links to special files, such as FIFOs and device nodes,
+    /// untouched, and leave special files out of directories copied by
+    /// [`Unfolder::deep`].
+    ///
+    /// By default, FIFOs and device nodes are recreated with the same type
+    /// and device numbers, which requires root for device nodes. Sockets
+    /// cannot be recreated, so unfolding symbolic links to them fails unless
+    /// they are skipped, while sockets inside directories copied by
+    /// [`Unfolder::deep`] are always left out, see [`Unfolded::skipped`].
+    pub fn skip_special(mut self, skip_special: bool) -> Unfolder {
+        self.skip_special = skip_special;
+        self
+    }
+
+    /// Mark the files and directories created when unfolding with extended
+    /// attributes recording the symbolic link they were unfolded from, which
+    /// can be read with [`read_mark`] and undone with [`refold`].
+    ///
+    /// Symbolic links copied as-is and hard links are never marked, since
+    /// hard links share their extended attributes with their targets. Only
+    /// supported on Unix.
+    pub fn mark(mut self, mark: bool) -> Unfolder {
+        self.mark = mark;
+        self
+    }
+
+    /// Only unfold symbolic links whose paths match any of the given glob
+    /// patterns, as given to [`Unfolder::unfold`].
+    ///
+    /// Defaults to unfolding every symbolic link.
+    pub fn include<I: IntoIterator<Item = Pattern>>(mut self, patterns: I) -> Unfolder {
+        self.filter.include = patterns.into_iter().collect();
+        self
+    }
+
+    /// Leave symbolic links whose paths match any of the given glob patterns
+    /// untouched, as given to [`Unfolder::unfold`].
+    ///
+    /// Excluded paths are skipped without being validated, so they may be
+    /// anything, not just symbolic links.
+    pub fn exclude<I: IntoIterator<Item = Pattern>>(mut self, patterns: I) -> Unfolder {
+        self.filter.exclude = patterns.into_iter().collect();
+        self
+    }
+
+    /// Only unfold symbolic links whose targets match any of the given glob
+    /// patterns, as absolute paths.
+    ///
+    /// Defaults to unfolding every symbolic link.
+    pub fn target_include<I: IntoIterator<Item = Pattern>>(mut self, patterns: I) -> Unfolder {
+        self.filter.target_include = patterns.into_iter().collect();
+        self
+    }
+
+    /// Leave symbolic links whose targets match any of the given glob
+    /// patterns untouched, as absolute paths.
+    pub fn target_exclude<I: IntoIterator<Item = Pattern>>(mut self, patterns: I) -> Unfolder {
+        self.filter.target_exclude = patterns.into_iter().collect();
+        self
+    }
+
+    /// Refuse to follow symbolic links to paths outside of the directory
+    /// `root`, if given.
+    ///
+    /// Every target followed while resolving a symbolic link is checked,
+    /// along with every symbolic link created inside unfolded directories
+    /// and every symbolic link followed by [`SymlinkPolicy::Follow`], so that
+    /// crafted symbolic links cannot copy or expose files from elsewhere.
+    /// Paths are compared after resolving the symbolic links in their parent
+    /// directories. Defaults to `None`, which allows any target.
+    pub fn within(mut self, root: Option<PathBuf>) -> Unfolder {
+        self.within = root;
+        self
+    }
+
+    /// Unfold a single symbolic link.
+    ///
+    /// The replacement for the symbolic link is built at a temporary path
+    /// next to it, and only swapped with the symbolic link once complete. If
+    /// an error occurs while unfolding, the symbolic link is left in its
+    /// original state. Returns why the symbolic link was skipped if it was
+    /// left untouched, or removed by [`BrokenPolicy::Remove`].
+    pub fn unfold<P: AsRef<Path>>(&self, symlink: P) -> Result<Unfolding> {
+        let path = symlink.as_ref();
+        let symlink = &try_absolute_path(path)?;
+        if let Some(skip) = self.try_find_skip(path, symlink)? {
+            if skip == Skip::Broken(BrokenPolicy::Remove) {
+                stage::try_remove(symlink).context(format!("Could not remove {:#?}.", symlink))?;
+            }
+            return Ok(Unfolding::Skipped(skip));
+        }
+        // the replacement is built at a staging path, and only swapped with
+        // the symbolic link once complete, so the symbolic link is untouched
+        // if anything goes wrong.
+        let staged = &stage::try_staging_path(symlink)?;
+        self.try_unfold(symlink, staged)
+            .map(Unfolding::Unfolded)
+            .map_err(|err| stage::discard(staged, err))
+    }
+
+    /// Unfold the outermost symbolic link among the ancestor directories of
+    /// `path` into a directory of symbolic links to the contents of its
+    /// target.
+    ///
+    /// Repeating this until it returns `None`, and then unfolding `path`
+    /// with [`Unfolder::unfold`], makes `path` local while everything next
+    /// to it stays a symbolic link. The ancestor is followed to its source,
+    /// since only a directory can be unfolded into, and is unfolded as if
+    /// [`Unfolder::dir_depth`] were 1, regardless of [`Unfolder::deep`] or
+    /// [`Unfolder::hardlink`]. Returns `None` if no ancestor of `path` is a
+    /// symbolic link, or if `path` is filtered out by
+    /// [`Unfolder::include`] or [`Unfolder::exclude`].
+    ///
+    /// Only ancestors inside the current directory, or inside
+    /// [`Unfolder::within`] if given, are unfolded. Symbolic links that this
+    /// directory is itself reached through, such as `/tmp` on macOS, are left
+    /// alone, and any other symbolic link outside of it is an error.
+    pub fn unfold_ancestor<P: AsRef<Path>>(&self, path: P) -> Result<Option<Unfolded>> {
+        let path = path.as_ref();
+        if !filter::is_path_selected(self, path) {
+            return Ok(None);
+        }
+        let absolute = try_absolute_path(path)?;
+        let root = match &self.within {
+            Some(root) => root.to_path_buf(),
+            None => std::env::current_dir()?,
+        };
+        let root = &root
+            .canonicalize()
+            .context(format!("{:#?} is unreachable.", root))?;
+        let ancestors: Vec<_> = absolute.ancestors().skip(1).collect();
+        let Some(ancestor) = ancestors.into_iter().rev().find(|ancestor| {
+            ancestor.is_symlink()
+                && !ancestor
+                    .canonicalize()
+                    .is_ok_and(|target| root.starts_with(target))
+        }) else {
+            return Ok(None);
+        };
+        let ancestor = &ancestor.to_path_buf();
+        if !try_canonical_parent(ancestor)?.starts_with(root) {
+            bail!(
+                "Refusing to unfold {:#?}, which is outside of {:#?}.",
+                ancestor,
+                root
+            );
+        }
+        validate_symlink(ancestor)?;
+        if !ancestor.is_dir() {
+            bail!("{:#?} does not lead to a directory.", ancestor);
+        }
+
+        let unfolder = Unfolder {
+            follow_to_source: true,
+            deep: false,
+            dir_depth: 1,
+            hardlink: false,
+            ..self.clone()
+        };
+        let staged = &stage::try_staging_path(ancestor)?;
+        unfolder
+            .try_unfold(ancestor, staged)
+            .map(Some)
+            .map_err(|err| stage::discard(staged, err))
+    }
+
+    /// Refresh a file or directory that was unfolded with
+    /// [`Unfolder::mark`] from its target.
+    ///
+    /// If the target has changed since `path` was unfolded, `path` is rebuilt
+    /// from it exactly as it was unfolded, using the options of this
+    /// `Unfolder` other than [`Unfolder::deep`], [`Unfolder::dir_depth`],
+    /// [`Unfolder::link_style`] and [`Unfolder::symlink_policy`], which are
+    /// read from the mark. For a directory of symbolic links, only changes to
+    /// the entries it holds count, not changes reached through them. If
+    /// `path` itself has changed since it was unfolded, it is left untouched
+    /// unless `force` is set, in which case its local modifications are lost.
+    /// Like unfolding, the replacement is built at a temporary path next to
+    /// `path`.
+    pub fn refresh<P: AsRef<Path>>(&self, path: P, force: bool) -> Result<RefreshStatus> {
+        let path = &try_absolute_path(path.as_ref())?;
+        let Some(mut mark) = read_mark(path)? else {
+            bail!("{:#?} is not marked as unfolded.", path);
+        };
+        // the replacement takes the same shape as when it was unfolded.
+        let unfolder = Unfolder {
+            dir_depth: mark.dir_depth,
+            link_style: mark.link_style,
+            symlink_policy: mark.symlink_policy,
+            ..self.clone()
+        };
+        let source_fingerprint = unfolder.try_fingerprint_source(mark.kind, &mark.target)?;
+        if source_fingerprint == mark.source_fingerprint {
+            return Ok(RefreshStatus::UpToDate);
+        } else if !force && fingerprint::try_fingerprint(path)? != mark.fingerprint {
+            return Ok(RefreshStatus::Modified);
+        }
+
+        mark.source_fingerprint = source_fingerprint;
+        let staged = &stage::try_staging_path(path)?;
+        unfolder
+            .try_refresh(path, staged, mark)
+            .map(|()| RefreshStatus::Updated)
+            .map_err(|err| stage::discard(staged, err))
+    }
+
+    /// Determine how a single symbolic link would be unfolded, without
+    /// modifying the filesystem.
+    ///
+    /// The symbolic link is validated and its target resolved exactly as
+    /// in [`Unfolder::unfold`], based on the current state of the
+    /// filesystem. Returns why the symbolic link would be skipped if it would
+    /// be left untouched, or removed by [`BrokenPolicy::Remove`].
+    pub fn plan<P: AsRef<Path>>(&self, symlink: P) -> Result<Unfolding> {
+        let path = symlink.as_ref();
+        let symlink = &try_absolute_path(path)?;
+        if let Some(skip) = self.try_find_skip(path, symlink)? {
+            return Ok(Unfolding::Skipped(skip));
+        }
+        let link_target = symlink.read_link()?;
+        let target = try_find_target(symlink, self.num_layers, self.follow_to_source)?;
+        let chain = try_find_chain(symlink, self.num_layers, self.follow_to_source)?;
+        self.try_check_within(&chain)?;
+        let kind = self.try_find_kind(symlink, &target)?;
+        let mut skipped = Vec::new();
+        let bytes = match kind {
+            UnfoldKind::File => target
+                .metadata()
+                .context(format!("{:#?} is unreachable.", target))?
+                .len(),
+            UnfoldKind::DeepDir => copy::try_measure_dir(self, &target, &mut skipped)?,
+            UnfoldKind::Dir => {
+                self.try_check_dir_within(&target, 1)?;
+                0
+            }
+            UnfoldKind::Symlink | UnfoldKind::HardLink | UnfoldKind::Special => 0,
+        };
+        Ok(Unfolding::Unfolded(Unfolded {
+            symlink: symlink.into(),
+            link_target,
+            target,
+            chain,
+            kind,
+            bytes,
+            skipped,
+        }))
+    }
+
+    /// Explain how a single symbolic link would be resolved, without
+    /// modifying the filesystem.
+    ///
+    /// Every hop in the chain of symbolic links is followed, regardless of
+    /// [`Unfolder::num_layers`], which only determines the hop that
+    /// unfolding would stop at. Unlike [`Unfolder::plan`], broken symbolic
+    /// links and loops are explained rather than treated as errors.
+    pub fn explain<P: AsRef<Path>>(&self, symlink: P) -> Result<Explanation> {
+        chain::try_explain(self, symlink.as_ref())
+    }
+
+    /// Determine why `symlink`, given as `path`, would be skipped, if at all.
+    ///
+    /// Paths filtered out by [`Unfolder::include`] and [`Unfolder::exclude`]
+    /// are skipped before they are validated, so they need not be symbolic
+    /// links at all.
+    fn try_find_skip(&self, path: &Path, symlink: &Path) -> Result<Option<Skip>> {
+        if self.num_layers == 0 && !self.follow_to_source {
+            return Ok(Some(Skip::NoLayers));
+        } else if !filter::is_path_selected(self, path) {
+            return Ok(Some(Skip::Filtered));
+        } else if self.broken != BrokenPolicy::Error && is_broken(symlink)? {
+            return Ok(Some(Skip::Broken(self.broken)));
+        }
+        validate_symlink(symlink)?;
+        if !filter::try_is_target_selected(self, symlink)? {
+            Ok(Some(Skip::Filtered))
+        } else if self.skip_special && self.try_targets_special(symlink)? {
+            Ok(Some(Skip::Special))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn try_find_kind(&self, symlink: &Path, target: &Path) -> Result<UnfoldKind> {
+        if target.is_symlink() {
+            Ok(UnfoldKind::Symlink)
+        } else if target.is_file() && self.hardlink {
+            Ok(UnfoldKind::HardLink)
+        } else if target.is_file() {
+            Ok(UnfoldKind::File)
+        } else if target.is_dir() && self.deep {
+            Ok(UnfoldKind::DeepDir)
+        } else if target.is_dir() {
+            Ok(UnfoldKind::Dir)
+        } else if let Some(metadata) = target.metadata().ok().filter(copy::is_special) {
+            copy::validate_special(target, &metadata)?;
+            Ok(UnfoldKind::Special)
+        } else {
+            bail!("Could not unfold {:#?}.", symlink);
+        }
+    }
+
+    /// Check that every path in `paths` is inside [`Unfolder::within`], if
+    /// set.
+    fn try_check_within<I>(&self, paths: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let Some(root) = &self.within else {
+            return Ok(());
+        };
+        let root = &root
+            .canonicalize()
+            .context(format!("{:#?} is unreachable.", root))?;
+        for path in paths {
+            let path = path.as_ref();
+            // symbolic links and missing paths are checked where they are,
+            // since where they lead is checked separately.
+            let canonical = match path.is_symlink() || !path.exists() {
+                true => try_canonical_parent(path)?,
+                false => path.canonicalize()?,
+            };
+            if !canonical.starts_with(root) {
+                bail!(
+                    "Refusing to follow {:#?}, which is outside of {:#?}.",
+                    path,
+                    root
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `path`, and every path its chain of symbolic links leads
+    /// to, is inside [`Unfolder::within`], if set.
+    pub(crate) fn try_check_chain_within(&self, path: &Path) -> Result<()> {
+        if self.within.is_none() {
+            return Ok(());
+        }
+        let (hops, _) = chain::try_walk_chain(path)?;
+        self.try_check_within(iter::once(path).chain(hops.iter().map(|hop| hop.path.as_path())))
+    }
+
+    /// Check every child of `target_dir` that [`Unfolder::try_dir_unfold_level`]
+    /// would link to at `depth`, and below it, with
+    /// [`Unfolder::try_check_chain_within`].
+    fn try_check_dir_within(&self, target_dir: &Path, depth: u8) -> Result<()> {
+        if self.within.is_none() {
+            return Ok(());
+        }
+        let children = target_dir
+            .read_dir()
+            .context(format!("Could not read contents of {:#?}", target_dir))?;
+        for child in children {
+            let child = child?;
+            let target = &child.path();
+            self.try_check_chain_within(target)?;
+            if depth < self.dir_depth && child.file_type()?.is_dir() {
+                self.try_check_dir_within(target, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the target `symlink` would be unfolded to is a special
+    /// file.
+    fn try_targets_special(&self, symlink: &Path) -> Result<bool> {
+        let target = try_find_target(symlink, self.num_layers, self.follow_to_source)?;
+        Ok(target
+            .symlink_metadata()
+            .is_ok_and(|metadata| copy::is_special(&metadata)))
+    }
+
+    fn try_unfold(&self, symlink: &PathBuf, staged: &Path) -> Result<Unfolded> {
+        let link_target = symlink.read_link()?;
+        let target = &try_find_target(symlink, self.num_layers, self.follow_to_source)?;
+        let chain = try_find_chain(symlink, self.num_layers, self.follow_to_source)?;
+        self.try_check_within(&chain)?;
+
+        let kind = self.try_find_kind(symlink, target)?;
+        let marked = self.mark
+            && matches!(
+                kind,
+                UnfoldKind::File | UnfoldKind::Dir | UnfoldKind::DeepDir
+            );
+        // the target is fingerprinted before it is copied, so that changes
+        // made to it while copying are picked up by a later refresh.
+        let source_fingerprint = match marked {
+            true => Some(self.try_fingerprint_source(kind, target)?),
+            false => None,
+        };
+        let mut skipped = Vec::new();
+        let bytes = self.try_build(kind, symlink, &link_target, staged, target, &mut skipped)?;
+        if let Some(source_fingerprint) = source_fingerprint {
+            let mark = Mark {
+                link_target: link_target.clone(),
+                target: target.into(),
+                kind,
+                fingerprint: fingerprint::try_fingerprint(staged)?,
+                source_fingerprint,
+                dir_depth: self.dir_depth,
+                link_style: self.link_style,
+                symlink_policy: self.symlink_policy,
+            };
+            mark::try_mark(staged, &mark).context(format!("Could not mark {:#?}.", staged))?;
+        }
+        stage::try_swap(staged, symlink)?;
+        Ok(Unfolded {
+            symlink: symlink.into(),
+            link_target,
+            target: target.into(),
+            chain,
+            kind,
+            bytes,
+            skipped,
+        })
+    }
+
+    /// Compute a fingerprint of the parts of `target` that unfolding it as
+    /// `kind` copies, so that changes to anything else do not call for a
+    /// refresh.
+    fn try_fingerprint_source(&self, kind: UnfoldKind, target: &Path) -> Result<String> {
+        match kind {
+            UnfoldKind::Dir => fingerprint::try_fingerprint_levels(target, self.dir_depth),
+            _ => fingerprint::try_fingerprint(target),
+        }
+    }
+
+    /// Build the replacement for `symlink` at `staged`, returning the number
+    /// of bytes copied and adding any sockets left out to `skipped`.
+    fn try_build(
+        &self,
+        kind: UnfoldKind,
+        symlink: &Path,
+        link_target: &Path,
+        staged: &Path,
+        target: &Path,
+        skipped: &mut Vec<PathBuf>,
+    ) -> Result<u64> {
+        match kind {
+            UnfoldKind::Symlink => {
+                try_symlink_unfold(staged, target)?;
+                Ok(0)
+            }
+            UnfoldKind::File => copy::try_copy_file(self, target, staged),
+            UnfoldKind::HardLink => {
+                try_hard_link_unfold(symlink, staged, target)?;
+                Ok(0)
+            }
+            UnfoldKind::Dir => {
+                self.try_dir_unfold(symlink, link_target, staged, target)?;
+                Ok(0)
+            }
+            UnfoldKind::DeepDir => self.try_deep_dir_unfold(symlink, staged, target, skipped),
+            UnfoldKind::Special => {
+                copy::try_copy_special(self, target, staged)?;
+                Ok(0)
+            }
+        }
+    }
+
+    fn try_refresh(&self, path: &Path, staged: &Path, mark: Mark) -> Result<()> {
+        self.try_check_within([&mark.target])?;
+        self.try_build(
+            mark.kind,
+            path,
+            &mark.link_target,
+            staged,
+            &mark.target,
+            &mut Vec::new(),
+        )?;
+        let mark = Mark {
+            fingerprint: fingerprint::try_fingerprint(staged)?,
+            ..mark
+        };
+        mark::try_mark(staged, &mark).context(format!("Could not mark {:#?}.", staged))?;
+        stage::try_swap(staged, path)
+    }
+
+    fn try_dir_unfold(
+        &self,
+        symlink_dir: &Path,
+        link_target: &Path,
+        staged_dir: &Path,
+        target_dir: &Path,
+    ) -> Result<()> {
+        let relative = match self.link_style {
+            LinkStyle::Absolute => false,
+            LinkStyle::Relative => true,
+            LinkStyle::Preserve => link_target.is_relative(),
+        };
+        // the children are written relative to where the directory ends up,
+        // not to the staging path it is built at.
+        let link_dir = match relative {
+            true => Some(relative_path(
+                &try_canonical_parent(symlink_dir)?,
+                &target_dir
+                    .canonicalize()
+                    .context(format!("{:#?} is unreachable.", target_dir))?,
+            )),
+            false => None,
+        };
+        let staged = try_canonical_parent(staged_dir)?;
+        self.try_dir_unfold_level(staged_dir, target_dir, link_dir.as_deref(), &staged, 1)
+    }
+
+    /// Create the directory `staged_dir` at `depth` levels into the unfolded
+    /// directory, filled with symbolic links to the contents of `target_dir`,
+    /// or with directories for subdirectories above [`Unfolder::dir_depth`].
+    /// `link_dir` is the relative path from `staged_dir` to `target_dir`,
+    /// when writing relative targets, and `staged` is the canonical path of
+    /// the whole unfolded directory being built.
+    fn try_dir_unfold_level(
+        &self,
+        staged_dir: &Path,
+        target_dir: &Path,
+        link_dir: Option<&Path>,
+        staged: &Path,
+        depth: u8,
+    ) -> Result<()> {
+        let metadata = target_dir
+            .metadata()
+            .context(format!("{:#?} is unreachable.", target_dir))?;
+        let canonical_dir = target_dir
+            .canonicalize()
+            .context(format!("{:#?} is unreachable.", target_dir))?;
+        std::fs::create_dir(staged_dir)
+            .context(format!("Could not create directory at {:#?}.", staged_dir))?;
+        let children = target_dir
+            .read_dir()
+            .context(format!("Could not read contents of {:#?}", target_dir))?;
+        for child in children {
+            let child = child?;
+            let target = &child.path();
+            let file_name = target.file_name().unwrap();
+            // the staging directory is built next to the symbolic link, so
+            // it is listed here when the target contains the link itself.
+            if canonical_dir.join(file_name) == staged {
+                continue;
+            }
+            let symlink = &staged_dir.join(file_name);
+            let link_dir = link_dir.map(|link_dir| link_dir.join(file_name));
+            self.try_check_chain_within(target)?;
+            if depth < self.dir_depth && child.file_type()?.is_dir() {
+                // one level deeper, relative targets have to climb out of the
+                // new directory first.
+                let link_dir = link_dir.map(|link_dir| Path::new("..").join(link_dir));
+                self.try_dir_unfold_level(symlink, target, link_dir.as_deref(), staged, depth + 1)?;
+                continue;
+            }
+            let link = link_dir.as_deref().unwrap_or(target);
+            symlink_auto(link, symlink)
+                .context(format!("Could not symlink {:#?} to {:#?}", target, symlink))?;
+        }
+        copy::try_preserve(self, target_dir, &metadata, staged_dir)
+    }
+
+    fn try_deep_dir_unfold(
+        &self,
+        symlink_dir: &Path,
+        staged_dir: &Path,
+        target_dir: &Path,
+        skipped: &mut Vec<PathBuf>,
+    ) -> Result<u64> {
+        let metadata = target_dir
+            .metadata()
+            .context(format!("{:#?} is unreachable.", target_dir))?;
+        std::fs::create_dir(staged_dir)
+            .context(format!("Could not create directory at {:#?}.", staged_dir))?;
+        // symbolic links inside are relocated relative to where the copy
+        // ends up, not to the staging path it is built at.
+        let location = try_canonical_parent(symlink_dir)?;
+        let bytes = copy::try_copy_dir_contents(self, staged_dir, &location, target_dir, skipped)?;
+        copy::try_preserve(self, target_dir, &metadata, staged_dir)?;
+        Ok(bytes)
+    }
+}
+
+fn try_absolute_path(path: &Path) -> Result<PathBuf> {
+    match path.is_absolute() {
+        true => Ok(path.into()),
+        false => Ok(std::env::current_dir()
+            .context("Current working directory is unreachable.")?
+            .join(path)),
+    }
+}
+
+fn validate_symlink(symlink: &Path) -> Result<()> {
+    if !symlink.is_symlink() {
+        bail!("{:#?} is not a symlink.", symlink)
+    } else if let Some(chain) = try_find_loop(symlink)? {
+        let chain: Vec<_> = chain.iter().map(|path| format!("{:#?}", path)).collect();
+        bail!(
+            "{:#?} leads to a symlink loop: {}",
+            symlink,
+            chain.join(" -> ")
+        )
+    } else if !symlink
+        .try_exists()
+        .context(format!("{:#?} is unreachable.", symlink))?
+    {
+        bail!("{:#?} is a broken symlink.", symlink)
+    };
+    Ok(())
+}
+
+/// Check whether `symlink` is a symbolic link whose chain of symbolic links
+/// leads to a path that does not exist, rather than looping.
+fn is_broken(symlink: &Path) -> Result<bool> {
+    let (hops, looped) = chain::try_walk_chain(symlink)?;
+    Ok(!looped && hops.last().is_some_and(|hop| hop.kind == HopKind::Missing))
+}
+
+/// Follow the chain of symbolic links from `symlink`, returning the chain if
+/// it loops back onto itself, ending with the first path seen twice.
+fn try_find_loop(symlink: &Path) -> Result<Option<Vec<PathBuf>>> {
+    let (hops, looped) = chain::try_walk_chain(symlink)?;
+    if !looped {
+        return Ok(None);
+    }
+    let mut chain = vec![symlink.to_path_buf()];
+    chain.extend(hops.into_iter().map(|hop| hop.path));
+    Ok(Some(chain))
+}
+
+fn try_find_target(symlink: &Path, num_layers: u8, follow_to_source: bool) -> Result<PathBuf> {
+    if follow_to_source {
+        return Ok(symlink.canonicalize()?);
+    }
+
+    let mut target = symlink.to_path_buf();
+    for _ in 0..num_layers {
+        if target.is_symlink() {
+            // have to join w/ parent dir because read_link gives a relative path.
+            target = target.parent().unwrap().join(target.read_link()?);
+        } else {
+            break;
+        };
+    }
+    Ok(target)
+}
+
+/// Find every target followed from `symlink`, in the same way as
+/// [`try_find_target`].
+fn try_find_chain(symlink: &Path, num_layers: u8, follow_to_source: bool) -> Result<Vec<PathBuf>> {
+    let mut chain = Vec::new();
+    let mut target = symlink.to_path_buf();
+    while target.is_symlink() && (follow_to_source || chain.len() < usize::from(num_layers)) {
+        target = target.parent().unwrap().join(target.read_link()?);
+        chain.push(target.clone());
+    }
+    Ok(chain)
+}
+
+fn try_symlink_unfold(staged: &Path, target: &Path) -> Result<()> {
+    symlink_auto(try_find_target(target, 1, false)?, staged).context(format!(
+        "Could not copy symlink {:#?} to {:#?}",
+        target, staged
+    ))?;
+    Ok(())
+}
+
+fn try_hard_link_unfold(symlink: &Path, staged: &Path, target: &Path) -> Result<()> {
+    match std::fs::hard_link(target, staged) {
+        Ok(()) => Ok(()),
+        Err(err) if is_cross_device(&err) => bail!(
+            "Could not hard link {:#?} to {:#?}: they are on different filesystems.",
+            symlink,
+            target
+        ),
+        Err(err) => Err(err).context(format!(
+            "Could not hard link {:#?} to {:#?}.",
+            symlink, target
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn is_cross_device(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(windows)]
+fn is_cross_device(err: &std::io::Error) -> bool {
+    // ERROR_NOT_SAME_DEVICE
+    err.raw_os_error() == Some(17)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_cross_device(_err: &std::io::Error) -> bool {
+    false
+}
+
+/// Make `path` absolute by canonicalizing its parent directory, keeping its
+/// file name even if it is a symbolic link.
+fn try_canonical_parent(path: &Path) -> Result<PathBuf> {
+    let path = try_absolute_path(path)?;
+    let file_name = path
+        .file_name()
+        .context(format!("{:#?} does not have a file name.", path))?;
+    let parent = path
+        .parent()
+        .unwrap()
+        .canonicalize()
+        .context(format!("{:#?} is unreachable.", path))?;
+    Ok(parent.join(file_name))
+}
+
+/// Resolve the `.` and `..` components of the absolute `path` lexically,
+/// without following symbolic links.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Compute a relative path from the directory `from_dir` to `to`, both of
+/// which must be absolute.
+fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from_dir.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        // nothing in common, such as different drives on windows.
+        return to.iter().collect();
+    }
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    path.extend(&to[common..]);
+    path
+}
+
+/// Replace whatever exists at `path` with a symbolic link to `link_target`.
+///
+/// The symbolic link is built at a staging path and swapped into place, so
+/// `path` is left untouched if an error occurs.
+fn try_relink(path: &Path, link_target: &Path) -> Result<()> {
+    let staged = &stage::try_staging_path(path)?;
+    symlink_auto(link_target, staged)?;
+    stage::try_swap(staged, path).map_err(|err| stage::discard(staged, err))
+}
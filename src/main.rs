@@ -1,26 +1,42 @@
 #![doc = include_str!("../README.md")]
 
-use anyhow::{anyhow, bail, Context, Result};
-use clap::error::ErrorKind::DisplayHelp;
-use clap::Parser;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use symlink::{remove_symlink_auto, remove_symlink_dir, remove_symlink_file, symlink_auto};
+use std::process::ExitCode;
+use unfold_symlinks::{
+    find_symlinks, read_mark, refold, Attribute, BrokenPolicy, Folder, HopKind, Journal, LinkStyle,
+    Pattern, Reflink, RefreshStatus, Skip, SymlinkPolicy, UnfoldKind, Unfolded, Unfolder,
+    Unfolding,
+};
 
 /// Unfold symbolic links to their targets.
 ///
 /// Symbolic links to files are replaced with copies of their targets.
 /// Symbolic links to directories are replaced with a directory whose
-/// contents are symbolic links to the contents of the targets. In both cases,
-/// the names of the original symbolic links are retained by the new files
-/// or directories.
+/// contents are symbolic links to the contents of the targets, or with a
+/// recursive copy of the targets when using the option '--deep'. In all
+/// cases, the names of the original symbolic links are retained by the new
+/// files or directories.
 ///
-/// If an error occurs while unfolding a symbolic link, that symbolic link will
-/// be reverted to its original state, if possible.
+/// The new file or directory is built next to the symbolic link and only
+/// swapped with it once complete, so if an error occurs while unfolding a
+/// symbolic link, that symbolic link is left in its original state.
 ///
 /// If multiple symbolic links are given as arguments, the symbolic links
 /// will be unfolded in the order they are given, and will only be unfolded if
 /// all prior symbolic links were successfully unfolded. Any symbolic link that
-/// was successfully unfolded prior to an error will not be reverted.
+/// was successfully unfolded prior to an error will not be reverted, unless
+/// the option '--atomic' is given, in which case every symbolic link is
+/// reverted so that either all or none of the symbolic links are unfolded.
+/// With the option '-k' or '--keep-going', every symbolic link is attempted
+/// regardless of prior errors.
+///
+/// Exits with code 0 if every symbolic link was unfolded, 1 if any symbolic
+/// link could not be unfolded, and 2 if the arguments are invalid.
 ///
 /// By default, symbolic links are unfolded to their immediate targets, which
 /// may also be symbolic links. To follow all symbolic links in the chain to
@@ -28,13 +44,55 @@ use symlink::{remove_symlink_auto, remove_symlink_dir, remove_symlink_file, syml
 /// up to NUM number of symbolic links in the chain, use the option '-n NUM'
 /// or '--num-layers NUM'. Note '-n 1' is equivalent to the default behavior,
 /// and '-n 0' will do nothing.
+///
+/// With the option '-r' or '--recursive', directories given as arguments are
+/// searched for symbolic links at any depth, and every symbolic link found is
+/// unfolded. All directories are searched before any symbolic link is
+/// unfolded, so symbolic links created by unfolding a symbolic link to a
+/// directory are not unfolded themselves.
+///
+/// To turn identical copies back into symbolic links, use the 'fold'
+/// subcommand. To restore symbolic links recorded with '--journal', use the
+/// 'undo' subcommand. To inspect or restore paths unfolded with '--mark',
+/// use the 'status' and 'refold' subcommands, and to update them from their
+/// targets, use the 'refresh' subcommand. To unfold a symbolic link named
+/// after a subcommand, prefix it with './'.
 #[derive(Debug, Parser)]
-#[command(version, about("Unfold symbolic links to their targets."), long_about)]
+#[command(
+    version,
+    about("Unfold symbolic links to their targets."),
+    long_about,
+    subcommand_negates_reqs(true),
+    args_conflicts_with_subcommands(true)
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Symbolic links to unfold.
-    #[arg(value_name("SYMLINK"), required(true))]
+    ///
+    /// With '-r' or '--recursive', directories may also be given, which will
+    /// be searched for symbolic links to unfold. If SYMLINK is '-', paths are
+    /// read from stdin instead, one per line, or separated by NUL characters
+    /// with '-0'. To unfold a symbolic link named '-', use './-'.
+    #[arg(value_name("SYMLINK"), required_unless_present("from_file"))]
     symlinks: Vec<PathBuf>,
 
+    /// Read paths of symbolic links to unfold from FILE.
+    ///
+    /// Paths are read one per line, or separated by NUL characters with
+    /// '-0', and are unfolded after any SYMLINK arguments.
+    #[arg(long("from-file"), value_name("FILE"))]
+    from_file: Option<PathBuf>,
+
+    /// Separate paths read from stdin or '--from-file' with NUL characters
+    /// instead of newlines.
+    ///
+    /// Use this with 'find -print0' to safely handle paths containing
+    /// newlines.
+    #[arg(short('0'), long("null"))]
+    null: bool,
+
     /// Follow symbolic links to their source.
     ///
     /// Incompatible with '-n' or '--num-layers'.
@@ -54,169 +112,1212 @@ struct Args {
     )]
     num_layers: u8,
 
+    /// Copy the entire contents of target directories.
+    ///
+    /// Symbolic links to directories are replaced with a recursive copy
+    /// of the target directory. Symbolic links inside the target directory
+    /// are handled according to '--deep-links'. To create a copy that
+    /// shares nothing with the original, use '--deep-links follow'.
+    #[arg(long("deep"))]
+    deep: bool,
+
+    /// Create real directories for the first DEPTH levels of target
+    /// directories.
+    ///
+    /// Below that depth, entries are symbolic links to the contents of the
+    /// target directory, so a nested directory can be edited without
+    /// copying the whole tree. A DEPTH of 1 only creates the unfolded
+    /// directory itself, which is the default. Symbolic links inside the
+    /// target directory are never descended into. Incompatible with
+    /// '--deep'.
+    #[arg(
+        long("dir-depth"),
+        value_name("DEPTH"),
+        default_value("1"),
+        hide_default_value(true),
+        value_parser(clap::value_parser!(u8).range(1..)),
+        conflicts_with("deep")
+    )]
+    dir_depth: u8,
+
+    /// How to handle symbolic links inside directories copied with '--deep'.
+    #[arg(
+        long("deep-links"),
+        value_name("POLICY"),
+        value_enum,
+        default_value_t,
+        requires("deep")
+    )]
+    deep_links: SymlinkPolicyArg,
+
+    /// How to write the targets of symbolic links created inside unfolded
+    /// directories.
+    ///
+    /// With 'relative', the unfolded directory keeps working when it is
+    /// moved or mounted elsewhere along with its target. With 'preserve',
+    /// targets are relative if the unfolded symbolic link's target was.
+    /// Has no effect with '--deep'.
+    #[arg(long("link-style"), value_name("STYLE"), value_enum, default_value_t)]
+    link_style: LinkStyleArg,
+
+    /// Replace symbolic links to files with hard links to their targets.
+    ///
+    /// The hard link shares its contents and metadata with the target, so
+    /// nothing is copied. Fails if the target is on a different filesystem
+    /// than the symbolic link. Symbolic links to directories are unfolded
+    /// as usual.
+    #[arg(long("hardlink"))]
+    hardlink: bool,
+
+    #[command(flatten)]
+    copy: CopyArgs,
+
+    /// Mark unfolded files and directories with where they came from.
+    ///
+    /// The original target of the symbolic link, the path of the target it
+    /// was unfolded to, and fingerprints of both are stored in extended
+    /// attributes prefixed with 'user.unfold.', which can be read with
+    /// 'unfold status', updated with 'unfold refresh', and undone with
+    /// 'unfold refold'. Symbolic links copied as-is and hard links are not
+    /// marked. Only supported on Unix.
+    #[arg(long("mark"))]
+    mark: bool,
+
+    /// Unfold all symbolic links in the given directories.
+    ///
+    /// Symbolic links given as arguments are unfolded as usual. Symbolic
+    /// links are not followed while searching the directories.
+    #[arg(short('r'), long("recursive"))]
+    recursive: bool,
+
+    /// Search at most DEPTH levels into each directory.
+    ///
+    /// A DEPTH of 1 only unfolds symbolic links directly inside the given
+    /// directories. Requires '-r' or '--recursive'.
+    #[arg(long("max-depth"), value_name("DEPTH"), requires("recursive"))]
+    max_depth: Option<usize>,
+
+    /// Unfold the symbolic links among the ancestor directories of each
+    /// argument first.
+    ///
+    /// Every symbolic link to a directory along the path is unfolded into a
+    /// directory of symbolic links, outermost first, so that only the path
+    /// itself is copied while everything next to it stays a symbolic link.
+    /// For example, 'unfold --materialize-path vendor/lib/config.toml' where
+    /// 'vendor' is a symbolic link. Only symbolic links inside the current
+    /// directory, or inside DIR with '--within', are unfolded, and any other
+    /// symbolic link along the path is an error. Incompatible with
+    /// '--dry-run'.
+    #[arg(long("materialize-path"), conflicts_with("dry_run"))]
+    materialize_path: bool,
+
+    /// Only unfold symbolic links whose paths match GLOB.
+    ///
+    /// May be given multiple times, in which case symbolic links matching any
+    /// GLOB are unfolded. Paths are matched as given, or as found with '-r',
+    /// and '*' also matches '/'. For example, '--include "*.txt"'.
+    #[arg(long("include"), value_name("GLOB"))]
+    include: Vec<Pattern>,
+
+    /// Leave symbolic links whose paths match GLOB untouched.
+    ///
+    /// May be given multiple times. Takes precedence over '--include'.
+    /// Excluded paths are skipped even if they are not symbolic links. For
+    /// example, '--exclude "**/.git/**"'.
+    #[arg(long("exclude"), value_name("GLOB"))]
+    exclude: Vec<Pattern>,
+
+    /// Only unfold symbolic links whose targets match GLOB.
+    ///
+    /// Like '--include', but matched against the absolute path of the target
+    /// each symbolic link would be unfolded to.
+    #[arg(long("target-include"), value_name("GLOB"))]
+    target_include: Vec<Pattern>,
+
+    /// Leave symbolic links whose targets match GLOB untouched.
+    ///
+    /// Like '--exclude', but matched against the absolute path of the target
+    /// each symbolic link would be unfolded to. For example,
+    /// '--target-exclude "/nix/store/**"'.
+    #[arg(long("target-exclude"), value_name("GLOB"))]
+    target_exclude: Vec<Pattern>,
+
+    /// How to handle broken symbolic links.
+    ///
+    /// A symbolic link is broken if any symbolic link in its chain targets a
+    /// path that does not exist. Only '--include' and '--exclude' apply to
+    /// broken symbolic links. Symbolic links removed with 'remove' are not
+    /// recorded by '--journal', and are not restored by '--atomic'.
+    #[arg(long("broken"), value_name("POLICY"), value_enum, default_value_t)]
+    broken: BrokenPolicyArg,
+
+    /// Revert all unfolded symbolic links if any symbolic link fails.
+    ///
+    /// Symbolic links are reverted in the reverse order they were unfolded,
+    /// restoring their original targets.
+    #[arg(long("atomic"))]
+    atomic: bool,
+
+    /// Attempt to unfold every symbolic link, even if some fail.
+    ///
+    /// Symbolic links that fail to unfold are reverted, and a summary is
+    /// printed once every symbolic link has been attempted. Exits with
+    /// code 1 if any symbolic link could not be unfolded. Incompatible with
+    /// '--atomic'.
+    #[arg(short('k'), long("keep-going"), conflicts_with("atomic"))]
+    keep_going: bool,
+
+    /// Print what would be done without unfolding anything.
+    ///
+    /// Every symbolic link is validated and its target resolved, then the
+    /// resolved target, the kind of unfold and the number of bytes that would
+    /// be copied are printed. Each symbolic link is checked against the
+    /// current state of the filesystem, ignoring changes that unfolding prior
+    /// arguments would make. Exits with an error if any symbolic link could
+    /// not be unfolded.
+    #[arg(long("dry-run"))]
+    dry_run: bool,
+
+    /// Record every unfolded symbolic link in the journal at PATH.
+    ///
+    /// The original target of each symbolic link is appended to the journal,
+    /// along with a fingerprint of what it was replaced with, so that it can
+    /// be restored later with 'unfold undo PATH'. A symbolic link that cannot
+    /// be recorded is reverted. Incompatible with '--dry-run'.
+    #[arg(long("journal"), value_name("PATH"), conflicts_with("dry_run"))]
+    journal: Option<PathBuf>,
+
+    /// Format of the output written to stdout.
+    ///
+    /// With 'json' or 'jsonl', a report is written for every symbolic link
+    /// attempted, holding the path given, the chain of targets followed from
+    /// it, the kind of unfold, the number of bytes copied, the outcome, and
+    /// the error if any. The outcome is one of 'unfolded', 'planned',
+    /// 'skipped', 'failed', 'reverted' or 'revert_failed', and symbolic links
+    /// reverted with '--atomic' are reported again once reverted. Error
+    /// messages are still printed to stderr.
+    #[arg(long("format"), value_name("FORMAT"), value_enum, default_value_t)]
+    format: Format,
+
     /// Set for verbose output.
     ///
     /// Error messages will still be printed to stderr
-    /// regardless of this option being set.
+    /// regardless of this option being set. Ignored with '--format'
+    /// other than 'text'.
+    #[arg(short('v'), long("verbose"))]
+    verbose: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    Fold(FoldArgs),
+    Undo(UndoArgs),
+    Status(StatusArgs),
+    Refold(RefoldArgs),
+    Refresh(RefreshArgs),
+    Explain(ExplainArgs),
+}
+
+/// Replace a file with a symbolic link to an identical file.
+///
+/// The inverse of unfolding. FILE is only replaced if its contents are
+/// byte-identical to the contents of REFERENCE. The symbolic link is built
+/// next to FILE and only swapped with it once complete, so if an error
+/// occurs, FILE is left in its original state.
+#[derive(Debug, clap::Args)]
+struct FoldArgs {
+    /// File to replace with a symbolic link.
+    #[arg(value_name("FILE"))]
+    file: PathBuf,
+
+    /// File for the symbolic link to target.
+    #[arg(value_name("REFERENCE"))]
+    reference: PathBuf,
+
+    /// Create a symbolic link with a target relative to its directory.
+    ///
+    /// By default, the symbolic link targets the absolute path of REFERENCE.
+    #[arg(long("relative"))]
+    relative: bool,
+
+    /// Set for verbose output.
     #[arg(short('v'), long("verbose"))]
     verbose: bool,
 }
 
-fn try_absolute_path(path: &PathBuf) -> Result<PathBuf> {
-    match path.is_absolute() {
-        true => Ok(path.into()),
-        false => Ok(std::env::current_dir()
-            .context("Current working directory is unreachable.")?
-            .join(path)),
+/// Restore symbolic links recorded in a journal.
+///
+/// Symbolic links are restored in the reverse order they were recorded,
+/// skipping any that were already restored. A symbolic link is only restored
+/// if what it was replaced with has not changed since it was unfolded,
+/// unless '--force' is given. Every symbolic link in the journal is
+/// attempted, and exits with code 1 if any could not be restored.
+#[derive(Debug, clap::Args)]
+struct UndoArgs {
+    /// Journal written with '--journal'.
+    #[arg(value_name("JOURNAL"))]
+    journal: PathBuf,
+
+    /// Restore symbolic links even if they changed since they were unfolded.
+    ///
+    /// Any changes made since unfolding are lost.
+    #[arg(long("force"))]
+    force: bool,
+
+    /// Set for verbose output.
+    #[arg(short('v'), long("verbose"))]
+    verbose: bool,
+}
+
+/// Report where paths unfolded with '--mark' came from.
+///
+/// For every marked path, prints the original target of the symbolic link it
+/// was unfolded from and the target it was unfolded to.
+#[derive(Debug, clap::Args)]
+struct StatusArgs {
+    /// Paths to report on.
+    #[arg(value_name("PATH"), required(true))]
+    paths: Vec<PathBuf>,
+}
+
+/// Restore paths unfolded with '--mark' to their original symbolic links.
+///
+/// Any changes made to the paths since they were unfolded are lost. Every
+/// path is attempted, and exits with code 1 if any could not be refolded.
+#[derive(Debug, clap::Args)]
+struct RefoldArgs {
+    /// Paths to refold.
+    #[arg(value_name("PATH"), required(true))]
+    paths: Vec<PathBuf>,
+
+    /// Set for verbose output.
+    #[arg(short('v'), long("verbose"))]
+    verbose: bool,
+}
+
+/// Update paths unfolded with '--mark' from their targets.
+///
+/// Every path whose target has changed since it was unfolded is rebuilt from
+/// the target, the same way it was unfolded. Paths that were modified since
+/// they were unfolded are reported and left untouched, unless '--force' is
+/// given. The directory depth, link style and handling of symbolic links
+/// inside deep copies are read from the mark, while other options affecting
+/// how paths are copied should match those used when unfolding. Exits with code 1 if any path was left untouched because of
+/// local modifications, or could not be refreshed.
+#[derive(Debug, clap::Args)]
+struct RefreshArgs {
+    /// Paths to refresh.
+    #[arg(value_name("PATH"), required(true))]
+    paths: Vec<PathBuf>,
+
+    /// Refresh paths even if they have local modifications, which are lost.
+    #[arg(long("force"))]
+    force: bool,
+
+    #[command(flatten)]
+    copy: CopyArgs,
+
+    /// Set for verbose output.
+    #[arg(short('v'), long("verbose"))]
+    verbose: bool,
+}
+
+/// Options affecting how targets are copied, shared by unfolding and
+/// 'unfold refresh'.
+#[derive(Debug, clap::Args)]
+struct CopyArgs {
+    /// Preserve the given attributes of targets when copying.
+    ///
+    /// ATTRIBUTES is a comma-separated list, applied to every file and
+    /// directory created when unfolding, including everything copied with
+    /// '--deep'. Copies of files always keep the permissions of their
+    /// targets, even if 'mode' is not given.
+    #[arg(
+        long("preserve"),
+        value_name("ATTRIBUTES"),
+        value_enum,
+        value_delimiter(',')
+    )]
+    preserve: Vec<AttributeArg>,
+
+    /// Same as '--preserve=mode,ownership,timestamps'.
+    #[arg(short('p'))]
+    preserve_common: bool,
+
+    /// Clone files with copy-on-write reflinks instead of copying them.
+    ///
+    /// Cloned files share their data with their targets until either is
+    /// modified, which saves space on filesystems that support it, such as
+    /// Btrfs and XFS.
+    #[arg(long("reflink"), value_name("WHEN"), value_enum, default_value_t)]
+    reflink: ReflinkArg,
+
+    /// Leave symbolic links to special files untouched.
+    ///
+    /// By default, symbolic links to FIFOs and device nodes are replaced with
+    /// new ones of the same type and device numbers, which requires root for
+    /// device nodes. Sockets cannot be recreated, and are always left out of
+    /// directories copied with '--deep' with a warning. Other special files
+    /// are also left out of them with this option.
+    #[arg(long("skip-special"))]
+    skip_special: bool,
+
+    /// Refuse to follow symbolic links to paths outside of DIR.
+    ///
+    /// Every target followed while resolving each symbolic link is checked,
+    /// along with every symbolic link created inside unfolded directories
+    /// and every symbolic link followed with '--deep-links follow'. Use this
+    /// when unfolding untrusted trees, so crafted symbolic links cannot copy
+    /// files such as '/etc/shadow' into them.
+    #[arg(long("within"), value_name("DIR"))]
+    within: Option<PathBuf>,
+}
+
+impl CopyArgs {
+    /// Configure `unfolder` with these options.
+    fn apply(self, unfolder: Unfolder) -> Unfolder {
+        let mut preserve = self.preserve;
+        if self.preserve_common {
+            preserve.extend([
+                AttributeArg::Mode,
+                AttributeArg::Ownership,
+                AttributeArg::Timestamps,
+            ]);
+        }
+        unfolder
+            .preserve(preserve.into_iter().map(Attribute::from))
+            .reflink(self.reflink.into())
+            .skip_special(self.skip_special)
+            .within(self.within)
     }
 }
 
-fn validate_symlink(symlink: &PathBuf) -> Result<()> {
-    if !symlink.is_symlink() {
-        bail!("{:#?} is not a symlink.", symlink)
-    } else if !symlink
-        .try_exists()
-        .context(format!("{:#?} is unreachable.", symlink))?
-    {
-        bail!("{:#?} is a broken symlink.", symlink)
-    };
-    Ok(())
+/// Format of the output written to stdout when unfolding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// A JSON array of reports, written once every symbolic link has been
+    /// attempted.
+    Json,
+    /// JSON Lines, with one report written as soon as each symbolic link has
+    /// been attempted.
+    Jsonl,
+}
+
+/// How symbolic links inside a directory are handled when it is deep copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum SymlinkPolicyArg {
+    /// Copy symbolic links, rewriting relative targets so that they lead to
+    /// the same paths, or to their copies inside the copied directory.
+    #[default]
+    Copy,
+    /// Copy the targets of symbolic links instead of the symbolic links.
+    Follow,
+    /// Leave symbolic links out of the copy.
+    Skip,
 }
 
-fn try_find_target(symlink: &Path, num_layers: u8, follow_to_source: bool) -> Result<PathBuf> {
-    if follow_to_source {
-        return Ok(symlink.canonicalize()?);
+impl From<SymlinkPolicyArg> for SymlinkPolicy {
+    fn from(policy: SymlinkPolicyArg) -> SymlinkPolicy {
+        match policy {
+            SymlinkPolicyArg::Copy => SymlinkPolicy::Copy,
+            SymlinkPolicyArg::Follow => SymlinkPolicy::Follow,
+            SymlinkPolicyArg::Skip => SymlinkPolicy::Skip,
+        }
     }
+}
 
-    let mut target = symlink.to_path_buf();
-    for _ in 0..num_layers {
-        if target.is_symlink() {
-            // have to join w/ parent dir because read_link gives a relative path.
-            target = target.parent().unwrap().join(target.read_link()?);
-        } else {
-            break;
-        };
+/// How the targets of symbolic links created inside unfolded directories are
+/// written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum LinkStyleArg {
+    /// Write absolute targets.
+    #[default]
+    Absolute,
+    /// Write targets relative to the location of each symbolic link.
+    Relative,
+    /// Write relative targets if the unfolded symbolic link had a relative
+    /// target, and absolute targets otherwise.
+    Preserve,
+}
+
+impl From<LinkStyleArg> for LinkStyle {
+    fn from(style: LinkStyleArg) -> LinkStyle {
+        match style {
+            LinkStyleArg::Absolute => LinkStyle::Absolute,
+            LinkStyleArg::Relative => LinkStyle::Relative,
+            LinkStyleArg::Preserve => LinkStyle::Preserve,
+        }
     }
-    Ok(target)
 }
 
-fn try_symlink_unfold(symlink: &PathBuf, target: &PathBuf) -> Result<()> {
-    remove_symlink_auto(symlink).context(format!("Could not unlink {:#?}.", symlink))?;
-    symlink_auto(try_find_target(target, 1, false)?, symlink).context(format!(
-        "Could not copy symlink {:#?} to {:#?}",
-        target, symlink
-    ))?;
-    Ok(())
+/// An attribute of a file or directory that can be preserved when copying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AttributeArg {
+    /// Permission bits.
+    Mode,
+    /// Access and modification times.
+    Timestamps,
+    /// Owning user and group. If not permitted to change the owning user,
+    /// only the owning group is preserved, if possible.
+    Ownership,
+    /// Extended attributes.
+    Xattr,
+}
+
+impl From<AttributeArg> for Attribute {
+    fn from(attribute: AttributeArg) -> Attribute {
+        match attribute {
+            AttributeArg::Mode => Attribute::Mode,
+            AttributeArg::Timestamps => Attribute::Timestamps,
+            AttributeArg::Ownership => Attribute::Ownership,
+            AttributeArg::Xattr => Attribute::Xattr,
+        }
+    }
 }
 
-fn try_file_unfold(symlink: &PathBuf, target: &PathBuf) -> Result<()> {
-    remove_symlink_file(symlink).context(format!("Could not unlink {:#?}.", symlink))?;
-    std::fs::copy(target, symlink).context(format!(
-        "Could not copy file {:#?} to {:#?}.",
-        target, symlink
-    ))?;
-    Ok(())
+/// Whether files are cloned with copy-on-write reflinks instead of copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ReflinkArg {
+    /// Clone files if the filesystem supports it, otherwise copy them.
+    #[default]
+    Auto,
+    /// Always clone files, failing if the filesystem does not support it.
+    Always,
+    /// Always copy files.
+    Never,
 }
 
-fn try_dir_unfold(symlink_dir: &PathBuf, target_dir: &PathBuf) -> Result<()> {
-    remove_symlink_dir(symlink_dir).context(format!("Could not unlink {:#?}.", symlink_dir))?;
-    std::fs::create_dir(symlink_dir)
-        .context(format!("Could not create directory at {:#?}.", symlink_dir))?;
-    let children = target_dir
-        .read_dir()
-        .context(format!("Could not read contents of {:#?}", target_dir))?;
-    for child in children {
-        let target = &child?.path();
-        let symlink = &symlink_dir.join(target.file_name().unwrap());
-        symlink_auto(target, symlink)
-            .context(format!("Could not symlink {:#?} to {:#?}", target, symlink))?;
+impl From<ReflinkArg> for Reflink {
+    fn from(reflink: ReflinkArg) -> Reflink {
+        match reflink {
+            ReflinkArg::Auto => Reflink::Auto,
+            ReflinkArg::Always => Reflink::Always,
+            ReflinkArg::Never => Reflink::Never,
+        }
     }
-    Ok(())
 }
 
-fn try_unfold(
-    symlink: &PathBuf,
-    num_layers: u8,
-    follow_to_source: bool,
-    verbose: bool,
-) -> Result<()> {
-    let target = &try_find_target(symlink, num_layers, follow_to_source)?;
-
-    if target.is_symlink() {
-        try_symlink_unfold(symlink, target)?;
-    } else if target.is_file() {
-        try_file_unfold(symlink, target)?;
-    } else if target.is_dir() {
-        try_dir_unfold(symlink, target)?;
-    } else {
-        bail!("Could not unfold {:#?}.", symlink);
+/// How broken symbolic links are handled when unfolding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum BrokenPolicyArg {
+    /// Fail with an error.
+    #[default]
+    Error,
+    /// Leave them untouched, printing a warning to stderr.
+    Skip,
+    /// Remove them.
+    Remove,
+    /// Leave them untouched.
+    Keep,
+}
+
+impl From<BrokenPolicyArg> for BrokenPolicy {
+    fn from(policy: BrokenPolicyArg) -> BrokenPolicy {
+        match policy {
+            BrokenPolicyArg::Error => BrokenPolicy::Error,
+            BrokenPolicyArg::Skip => BrokenPolicy::Skip,
+            BrokenPolicyArg::Remove => BrokenPolicy::Remove,
+            BrokenPolicyArg::Keep => BrokenPolicy::Keep,
+        }
     }
+}
 
-    if verbose {
-        println!(
-            "Successfully unfolded {:#?} targeting {:#?}",
-            symlink, target,
-        );
+/// What happened to a symbolic link.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Unfolded,
+    Planned,
+    Skipped,
+    Failed,
+    Reverted,
+    RevertFailed,
+}
+
+/// Machine-readable report of what happened to a symbolic link.
+#[derive(Debug, Serialize)]
+struct Report {
+    symlink: PathBuf,
+    chain: Vec<PathBuf>,
+    kind: Option<UnfoldKind>,
+    bytes: Option<u64>,
+    outcome: Outcome,
+    error: Option<String>,
+}
+
+impl Report {
+    fn new<P: AsRef<Path>>(
+        symlink: P,
+        outcome: Outcome,
+        unfolded: Option<&Unfolded>,
+        err: Option<&anyhow::Error>,
+    ) -> Report {
+        Report {
+            symlink: symlink.as_ref().into(),
+            chain: unfolded
+                .map(|unfolded| unfolded.chain.clone())
+                .unwrap_or_default(),
+            kind: unfolded.map(|unfolded| unfolded.kind),
+            bytes: unfolded.map(|unfolded| unfolded.bytes),
+            outcome,
+            error: err.map(|err| format!("{:#}", err)),
+        }
+    }
+
+    /// Report that `symlink` could not be unfolded, along with as much of its
+    /// chain as `unfolder` can still resolve.
+    fn failed<P: AsRef<Path>>(unfolder: &Unfolder, symlink: P, err: &anyhow::Error) -> Report {
+        let symlink = symlink.as_ref();
+        let chain = match unfolder.explain(symlink) {
+            Ok(explanation) => {
+                let len = explanation
+                    .stop
+                    .map_or(explanation.hops.len(), |stop| stop + 1);
+                explanation
+                    .hops
+                    .into_iter()
+                    .take(len)
+                    .map(|hop| hop.path)
+                    .collect()
+            }
+            Err(_) => Vec::new(),
+        };
+        Report {
+            chain,
+            ..Report::new(symlink, Outcome::Failed, None, Some(err))
+        }
     }
-    Ok(())
 }
 
-fn try_revert(symlink: &PathBuf, target: &PathBuf) -> Result<()> {
-    let exists = symlink.try_exists()?;
-    if exists && symlink.is_file() {
-        std::fs::remove_file(symlink)?;
-    } else if exists && symlink.is_dir() {
-        std::fs::remove_dir_all(symlink)?;
+/// Writes reports to stdout in the requested format.
+struct Reporter {
+    format: Format,
+    reports: Vec<Report>,
+}
+
+impl Reporter {
+    fn new(format: Format) -> Reporter {
+        Reporter {
+            format,
+            reports: Vec::new(),
+        }
     }
-    symlink::symlink_auto(target, symlink)?;
-    Ok(())
+
+    fn report(&mut self, report: Report) -> Result<()> {
+        match self.format {
+            Format::Text => (),
+            Format::Json => self.reports.push(report),
+            Format::Jsonl => println!("{}", serde_json::to_string(&report)?),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.format == Format::Json {
+            println!("{}", serde_json::to_string_pretty(&self.reports)?);
+        }
+        Ok(())
+    }
+}
+
+/// Exit code used when some symbolic links could not be unfolded.
+const PARTIAL_FAILURE: u8 = 1;
+
+/// Exit code used when the command line arguments are invalid.
+const USAGE_ERROR: u8 = 2;
+
+/// Show every hop in the chains of symbolic links, without modifying them.
+///
+/// For every hop, prints the target exactly as read from the previous
+/// symbolic link, the path it resolves to, and what exists at that path.
+/// Broken chains and loops are shown up to where they break or repeat. Also
+/// reports the hop that unfolding with the given '-f' or '-n' would stop at.
+/// Every symbolic link is attempted, and exits with code 1 if any could not
+/// be explained.
+#[derive(Debug, clap::Args)]
+struct ExplainArgs {
+    /// Symbolic links to explain.
+    #[arg(value_name("SYMLINK"), required(true))]
+    symlinks: Vec<PathBuf>,
+
+    /// Report the hop that following symbolic links to their source would
+    /// stop at.
+    #[arg(short('f'), long("follow-to-source"), conflicts_with("num_layers"))]
+    follow_to_source: bool,
+
+    /// Report the hop that following up to NUM symbolic links would stop at.
+    #[arg(
+        short('n'),
+        long("num-layers"),
+        value_name("NUM"),
+        default_value("1"),
+        hide_default_value(true),
+        conflicts_with("follow_to_source")
+    )]
+    num_layers: u8,
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     // The default error message format for clap is "error: {err}".
     // In contrast, anyhow error messages are prepended with "Error: "
     // when formatted, creating an output of "Error: {err}". To make
     // the capitalization consistent, we strip out the beginning of
-    // clap's error message, leaving only "{err}", and then use
-    // anyhow to format the error.
-    let args = Args::try_parse().map_err(|err| {
-        // Help text in clap is an error type, so we need to
-        // special-case it when mapping the clap error.
-        if err.kind() == DisplayHelp {
+    // clap's error message, leaving only "{err}", and then format
+    // the error the same way anyhow does.
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        // Help and version text in clap are error types, so we need to
+        // special-case them when handling the clap error.
+        Err(err) if !err.use_stderr() => {
             print!("{}", err);
-            std::process::exit(0);
+            return Ok(ExitCode::SUCCESS);
         }
-        let err_str = err.to_string();
-        match err_str.starts_with("error: ") {
-            true => anyhow!("{}", err_str.split_at(7).1),
-            false => err.into(),
+        Err(err) => {
+            let err_str = err.to_string();
+            eprintln!(
+                "Error: {}",
+                err_str.strip_prefix("error: ").unwrap_or(&err_str)
+            );
+            return Ok(ExitCode::from(USAGE_ERROR));
+        }
+    };
+
+    match args.command {
+        Some(Command::Fold(args)) => {
+            let folded = Folder::new()
+                .relative(args.relative)
+                .fold(&args.file, &args.reference)?;
+            if args.verbose {
+                println!(
+                    "Successfully folded {:#?} into {:#?}",
+                    folded.file, args.reference
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
         }
-    })?;
+        Some(Command::Undo(args)) => return undo(args),
+        Some(Command::Status(args)) => return status(args),
+        Some(Command::Refold(args)) => return refold_all(args),
+        Some(Command::Refresh(args)) => return refresh(args),
+        Some(Command::Explain(args)) => return explain(args),
+        None => (),
+    }
 
-    if args.num_layers == 0 {
+    // in the machine-readable formats, every symbolic link is reported as
+    // skipped instead.
+    let text = args.format == Format::Text;
+    if args.num_layers == 0 && text {
         if args.verbose {
             println!("Did nothing. :/");
         }
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
+    let verbose = args.verbose && text;
 
-    for symlink in args.symlinks {
-        let symlink = &try_absolute_path(&symlink)?;
-        validate_symlink(symlink)?;
-        let target = &try_find_target(symlink, 1, false)?;
-        try_unfold(
-            symlink,
-            args.num_layers,
-            args.follow_to_source,
-            args.verbose,
+    let symlinks = try_collect_symlinks(&args)?;
+    let unfolder = Unfolder::new()
+        .num_layers(args.num_layers)
+        .follow_to_source(args.follow_to_source)
+        .deep(args.deep)
+        .dir_depth(args.dir_depth)
+        .symlink_policy(args.deep_links.into())
+        .broken(args.broken.into())
+        .link_style(args.link_style.into())
+        .hardlink(args.hardlink)
+        .mark(args.mark)
+        .include(args.include)
+        .exclude(args.exclude)
+        .target_include(args.target_include)
+        .target_exclude(args.target_exclude);
+    let unfolder = args.copy.apply(unfolder);
+
+    let journal = args.journal.map(Journal::open).transpose()?;
+    let reporter = Reporter::new(args.format);
+    let materialize = args.materialize_path;
+    if args.dry_run {
+        plan_all(&unfolder, symlinks, reporter)
+    } else if args.keep_going {
+        unfold_keep_going(&unfolder, journal, symlinks, reporter, materialize, verbose)
+    } else {
+        unfold_all(
+            &unfolder,
+            journal,
+            symlinks,
+            reporter,
+            materialize,
+            args.atomic,
+            verbose,
         )
-        .or_else(|err| match try_revert(symlink, target) {
-            Ok(()) => Err(err),
-            Err(revert_err) => {
-                Err(err).context(format!("Could not revert {:#?}: {}", symlink, revert_err))
+    }
+}
+
+/// Find the symbolic links to unfold, from the arguments, stdin and
+/// '--from-file', searching directories with '-r'.
+fn try_collect_symlinks(args: &Args) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for path in &args.symlinks {
+        if path == Path::new("-") {
+            paths.extend(
+                read_paths(std::io::stdin().lock(), args.null)
+                    .context("Could not read symbolic links from stdin.")?,
+            );
+        } else {
+            paths.push(path.clone());
+        }
+    }
+    if let Some(from_file) = &args.from_file {
+        let file = File::open(from_file).context(format!("Could not open {:#?}.", from_file))?;
+        paths.extend(read_paths(file, args.null).context(format!(
+            "Could not read symbolic links from {:#?}.",
+            from_file
+        ))?);
+    }
+
+    let mut symlinks = Vec::new();
+    for path in paths {
+        if args.recursive && path.is_dir() && !path.is_symlink() {
+            symlinks.extend(find_symlinks(&path, args.max_depth)?);
+        } else {
+            symlinks.push(path);
+        }
+    }
+    Ok(symlinks)
+}
+
+/// Print and report what would be done for every symbolic link in
+/// `symlinks`, for '--dry-run'.
+fn plan_all(
+    unfolder: &Unfolder,
+    symlinks: Vec<PathBuf>,
+    mut reporter: Reporter,
+) -> Result<ExitCode> {
+    let mut num_failed = 0;
+    for symlink in symlinks {
+        match unfolder.plan(&symlink) {
+            Ok(Unfolding::Unfolded(plan)) => {
+                warn_skipped(&plan);
+                if reporter.format == Format::Text {
+                    println!(
+                        "Would unfold {:#?} targeting {:#?} ({}, {} bytes)",
+                        plan.symlink, plan.target, plan.kind, plan.bytes,
+                    );
+                }
+                reporter.report(Report::new(symlink, Outcome::Planned, Some(&plan), None))?;
+            }
+            Ok(Unfolding::Skipped(_)) => {
+                reporter.report(Report::new(symlink, Outcome::Skipped, None, None))?
+            }
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                num_failed += 1;
+                reporter.report(Report::failed(unfolder, symlink, &err))?;
+            }
+        }
+    }
+    reporter.finish()?;
+    if num_failed > 0 {
+        bail!("{} symbolic link(s) could not be unfolded.", num_failed);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Attempt to unfold every symbolic link in `symlinks`, for '--keep-going',
+/// printing a summary of those that failed.
+fn unfold_keep_going(
+    unfolder: &Unfolder,
+    mut journal: Option<Journal>,
+    symlinks: Vec<PathBuf>,
+    mut reporter: Reporter,
+    materialize: bool,
+    verbose: bool,
+) -> Result<ExitCode> {
+    let num_symlinks = symlinks.len();
+    let mut num_skipped = 0;
+    let mut failed = Vec::new();
+    for symlink in symlinks {
+        match try_unfold(unfolder, journal.as_mut(), &symlink, materialize) {
+            Ok(unfolding) => {
+                let skipped =
+                    report_unfolding(&mut reporter, symlink, unfolding, verbose, &mut Vec::new())?;
+                num_skipped += usize::from(skipped);
+            }
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                reporter.report(Report::failed(unfolder, &symlink, &err))?;
+                failed.push(symlink);
+            }
+        }
+    }
+    let text = reporter.format == Format::Text;
+    reporter.finish()?;
+    if text {
+        println!(
+            "Unfolded {} of {} symbolic link(s).",
+            num_symlinks - failed.len() - num_skipped,
+            num_symlinks,
+        );
+    }
+    if failed.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+    if text {
+        println!("Could not unfold:");
+        for symlink in failed {
+            println!("    {:#?}", symlink);
+        }
+    }
+    Ok(ExitCode::from(PARTIAL_FAILURE))
+}
+
+/// Unfold every symbolic link in `symlinks`, stopping at the first that
+/// fails. With `atomic`, everything unfolded before it is reverted.
+fn unfold_all(
+    unfolder: &Unfolder,
+    mut journal: Option<Journal>,
+    symlinks: Vec<PathBuf>,
+    mut reporter: Reporter,
+    materialize: bool,
+    atomic: bool,
+    verbose: bool,
+) -> Result<ExitCode> {
+    let mut unfolded = Vec::new();
+    for symlink in symlinks {
+        match try_unfold(unfolder, journal.as_mut(), &symlink, materialize) {
+            Ok(unfolding) => {
+                report_unfolding(&mut reporter, symlink, unfolding, verbose, &mut unfolded)?;
+            }
+            Err(mut err) => {
+                reporter.report(Report::failed(unfolder, symlink, &err))?;
+                if atomic {
+                    for (symlink, unfolded_symlink) in unfolded.iter().rev() {
+                        match unfolded_symlink.revert() {
+                            Ok(()) => {
+                                if verbose {
+                                    println!("Reverted {:#?}", unfolded_symlink.symlink);
+                                }
+                                reporter.report(Report::new(
+                                    symlink,
+                                    Outcome::Reverted,
+                                    Some(unfolded_symlink),
+                                    None,
+                                ))?;
+                            }
+                            Err(revert_err) => {
+                                reporter.report(Report::new(
+                                    symlink,
+                                    Outcome::RevertFailed,
+                                    Some(unfolded_symlink),
+                                    Some(&revert_err),
+                                ))?;
+                                err = err.context(format!("{:#}", revert_err));
+                            }
+                        }
+                    }
+                }
+                reporter.finish()?;
+                return Err(err);
+            }
+        }
+    }
+    reporter.finish()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Print and report what [`try_unfold`] did for `symlink`, adding every
+/// symbolic link it unfolded to `unfolded`, along with the path it is
+/// reported under. Returns whether `symlink` itself was skipped.
+fn report_unfolding(
+    reporter: &mut Reporter,
+    symlink: PathBuf,
+    (ancestors, unfolding): (Vec<Unfolded>, Unfolding),
+    verbose: bool,
+    unfolded: &mut Vec<(PathBuf, Unfolded)>,
+) -> Result<bool> {
+    for ancestor in ancestors {
+        print_unfolded(&ancestor, verbose);
+        reporter.report(Report::new(
+            &ancestor.symlink,
+            Outcome::Unfolded,
+            Some(&ancestor),
+            None,
+        ))?;
+        unfolded.push((ancestor.symlink.clone(), ancestor));
+    }
+    match unfolding {
+        Unfolding::Unfolded(unfolded_symlink) => {
+            print_unfolded(&unfolded_symlink, verbose);
+            reporter.report(Report::new(
+                &symlink,
+                Outcome::Unfolded,
+                Some(&unfolded_symlink),
+                None,
+            ))?;
+            unfolded.push((symlink, unfolded_symlink));
+            Ok(false)
+        }
+        Unfolding::Skipped(skip) => {
+            print_skipped(&symlink, skip, verbose);
+            reporter.report(Report::new(symlink, Outcome::Skipped, None, None))?;
+            Ok(true)
+        }
+    }
+}
+
+/// Read paths separated by newlines, or by NUL characters if `null` is set.
+/// Empty paths are ignored.
+fn read_paths<R: Read>(mut reader: R, null: bool) -> Result<Vec<PathBuf>> {
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let delimiter = if null { b'\0' } else { b'\n' };
+    input
+        .split(|byte| *byte == delimiter)
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(std::ffi::OsStr::from_bytes(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    Ok(String::from_utf8(bytes.to_vec())?.into())
+}
+
+/// Unfold `symlink`, recording it in `journal` if given. With `materialize`,
+/// the symbolic links among its ancestor directories are unfolded and
+/// recorded first, and returned along with it. If anything cannot be
+/// unfolded or recorded, whatever was already unfolded for `symlink` is
+/// reverted.
+fn try_unfold<P: AsRef<Path>>(
+    unfolder: &Unfolder,
+    journal: Option<&mut Journal>,
+    symlink: P,
+    materialize: bool,
+) -> Result<(Vec<Unfolded>, Unfolding)> {
+    let mut unfolded = Vec::new();
+    let result = try_unfold_steps(
+        unfolder,
+        journal,
+        symlink.as_ref(),
+        materialize,
+        &mut unfolded,
+    );
+    match result {
+        Ok(None) => {
+            let last = unfolded.pop().unwrap();
+            Ok((unfolded, Unfolding::Unfolded(last)))
+        }
+        Ok(Some(skip)) => Ok((unfolded, Unfolding::Skipped(skip))),
+        Err(mut err) => {
+            // inner paths depend on the ancestors unfolded before them, so
+            // steps are reverted in the reverse order they were unfolded.
+            for step in unfolded.iter().rev() {
+                if let Err(revert_err) = step.revert() {
+                    err = err.context(format!("{:#}", revert_err));
+                }
             }
-        })?;
+            Err(err)
+        }
+    }
+}
+
+/// Unfold and record every step of [`try_unfold`] into `unfolded`, returning
+/// why `symlink` itself was skipped, if it was.
+fn try_unfold_steps(
+    unfolder: &Unfolder,
+    mut journal: Option<&mut Journal>,
+    symlink: &Path,
+    materialize: bool,
+    unfolded: &mut Vec<Unfolded>,
+) -> Result<Option<Skip>> {
+    let mut record = |step: Unfolded| -> Result<()> {
+        unfolded.push(step);
+        match journal.as_deref_mut() {
+            Some(journal) => journal.record(unfolded.last().unwrap()),
+            None => Ok(()),
+        }
+    };
+    if materialize {
+        while let Some(ancestor) = unfolder.unfold_ancestor(symlink)? {
+            record(ancestor)?;
+        }
+    }
+    match unfolder.unfold(symlink)? {
+        Unfolding::Unfolded(step) => record(step).map(|()| None),
+        Unfolding::Skipped(skip) => Ok(Some(skip)),
+    }
+}
+
+/// Print that `unfolded` was unfolded if `verbose`. Sockets left out of it
+/// are always warned about.
+fn print_unfolded(unfolded: &Unfolded, verbose: bool) {
+    warn_skipped(unfolded);
+    if verbose {
+        println!(
+            "Successfully unfolded {:#?} targeting {:#?}",
+            unfolded.symlink, unfolded.target,
+        );
     }
+}
+
+/// Warn about every socket left out of `unfolded`.
+fn warn_skipped(unfolded: &Unfolded) {
+    for path in &unfolded.skipped {
+        eprintln!("Warning: Skipped {:#?}, which is a socket", path);
+    }
+}
+
+/// Print why `symlink` was skipped. Broken symbolic links skipped with a
+/// warning are always printed, and everything else only if `verbose`.
+fn print_skipped(symlink: &Path, skip: Skip, verbose: bool) {
+    match skip {
+        Skip::Broken(BrokenPolicy::Skip) => {
+            eprintln!("Warning: Skipped {:#?}, which is a broken symlink", symlink)
+        }
+        _ if !verbose => (),
+        Skip::NoLayers => (),
+        Skip::Filtered => println!("Skipped {:#?}, which is filtered out", symlink),
+        Skip::Broken(BrokenPolicy::Remove) => {
+            println!("Removed {:#?}, which is a broken symlink", symlink)
+        }
+        Skip::Broken(_) => println!("Kept {:#?}, which is a broken symlink", symlink),
+        Skip::Special => println!("Skipped {:#?}, which targets a special file", symlink),
+    }
+}
 
-    Ok(())
+fn undo(args: UndoArgs) -> Result<ExitCode> {
+    let mut num_failed = 0;
+    for entry in Journal::read(&args.journal)?.iter().rev() {
+        match entry.undo(args.force) {
+            Ok(true) if args.verbose => println!(
+                "Restored {:#?} targeting {:#?}",
+                entry.symlink, entry.link_target
+            ),
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                num_failed += 1;
+            }
+        }
+    }
+    if num_failed > 0 {
+        bail!("{} symbolic link(s) could not be restored.", num_failed);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn status(args: StatusArgs) -> Result<ExitCode> {
+    let mut num_failed = 0;
+    for path in args.paths {
+        match read_mark(&path) {
+            Ok(Some(mark)) => println!(
+                "{:#?} was unfolded from a symbolic link to {:#?} targeting {:#?}",
+                path, mark.link_target, mark.target
+            ),
+            Ok(None) => println!("{:#?} is not marked as unfolded.", path),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                num_failed += 1;
+            }
+        }
+    }
+    if num_failed > 0 {
+        bail!("{} path(s) could not be read.", num_failed);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn refold_all(args: RefoldArgs) -> Result<ExitCode> {
+    let mut num_failed = 0;
+    for path in args.paths {
+        match refold(&path) {
+            Ok(mark) if args.verbose => {
+                println!("Refolded {:#?} targeting {:#?}", path, mark.link_target)
+            }
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                num_failed += 1;
+            }
+        }
+    }
+    if num_failed > 0 {
+        bail!("{} path(s) could not be refolded.", num_failed);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn refresh(args: RefreshArgs) -> Result<ExitCode> {
+    let unfolder = args.copy.apply(Unfolder::new());
+
+    let mut num_failed = 0;
+    for path in args.paths {
+        match unfolder.refresh(&path, args.force) {
+            Ok(RefreshStatus::Updated) => println!("Updated {:#?}", path),
+            Ok(RefreshStatus::UpToDate) if args.verbose => {
+                println!("{:#?} is up to date.", path)
+            }
+            Ok(RefreshStatus::UpToDate) => (),
+            Ok(RefreshStatus::Modified) => {
+                println!("{:#?} has local modifications that would be lost.", path);
+                num_failed += 1;
+            }
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                num_failed += 1;
+            }
+        }
+    }
+    match num_failed {
+        0 => Ok(ExitCode::SUCCESS),
+        _ => Ok(ExitCode::from(PARTIAL_FAILURE)),
+    }
+}
+
+fn explain(args: ExplainArgs) -> Result<ExitCode> {
+    let unfolder = Unfolder::new()
+        .num_layers(args.num_layers)
+        .follow_to_source(args.follow_to_source);
+
+    let mut num_failed = 0;
+    for symlink in args.symlinks {
+        let explanation = match unfolder.explain(&symlink) {
+            Ok(explanation) => explanation,
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                num_failed += 1;
+                continue;
+            }
+        };
+        println!("{:#?}", explanation.symlink);
+        for (i, hop) in explanation.hops.iter().enumerate() {
+            println!(
+                "  {}. {:#?} -> {:#?} ({})",
+                i + 1,
+                hop.link_target,
+                hop.path,
+                hop.kind
+            );
+        }
+        match explanation.stop {
+            _ if explanation.looped => println!("  The chain loops, so it cannot be unfolded."),
+            None if explanation.hops.last().map(|hop| hop.kind) == Some(HopKind::Missing) => {
+                println!("  The chain is broken, so it cannot be unfolded.")
+            }
+            None => println!("  Unfolding would leave it untouched."),
+            Some(i) => println!("  Unfolding would stop at hop {}.", i + 1),
+        }
+    }
+    if num_failed > 0 {
+        bail!("{} symbolic link(s) could not be explained.", num_failed);
+    }
+    Ok(ExitCode::SUCCESS)
 }
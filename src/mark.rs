@@ -0,0 +1,185 @@
+use crate::{try_relink, LinkStyle, SymlinkPolicy, UnfoldKind};
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Prefix of the extended attributes used to mark unfolded paths.
+#[cfg(unix)]
+const PREFIX: &str = "user.unfold.";
+/// Extended attribute holding the original target of the symbolic link.
+#[cfg(unix)]
+const LINK_TARGET: &str = "user.unfold.link_target";
+/// Extended attribute holding the path of the target that was unfolded to.
+#[cfg(unix)]
+const TARGET: &str = "user.unfold.target";
+/// Extended attribute holding what the symbolic link was replaced with.
+#[cfg(unix)]
+const KIND: &str = "user.unfold.kind";
+/// Extended attribute holding the fingerprint of the unfolded path.
+#[cfg(unix)]
+const FINGERPRINT: &str = "user.unfold.fingerprint";
+/// Extended attribute holding the fingerprint of the target.
+#[cfg(unix)]
+const SOURCE_FINGERPRINT: &str = "user.unfold.source_fingerprint";
+/// Extended attribute holding the number of levels of real directories.
+#[cfg(unix)]
+const DIR_DEPTH: &str = "user.unfold.dir_depth";
+/// Extended attribute holding how the targets of symbolic links were written.
+#[cfg(unix)]
+const LINK_STYLE: &str = "user.unfold.link_style";
+/// Extended attribute holding how symbolic links inside deep copies were
+/// handled.
+#[cfg(unix)]
+const SYMLINK_POLICY: &str = "user.unfold.symlink_policy";
+
+/// Where a file or directory marked when unfolding came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark {
+    /// Original target of the symbolic link, exactly as read from it.
+    pub link_target: PathBuf,
+    /// Path of the target that the symbolic link was unfolded to.
+    pub target: PathBuf,
+    /// What the symbolic link was replaced with.
+    pub kind: UnfoldKind,
+    /// Fingerprint of the marked path when it was unfolded, used to detect
+    /// local modifications.
+    pub fingerprint: String,
+    /// Fingerprint of the target when it was unfolded, used to detect
+    /// changes to the target.
+    pub source_fingerprint: String,
+    /// Number of levels of real directories created when unfolding, see
+    /// [`Unfolder::dir_depth`](crate::Unfolder::dir_depth).
+    pub dir_depth: u8,
+    /// How the targets of symbolic links created when unfolding were
+    /// written, see [`Unfolder::link_style`](crate::Unfolder::link_style).
+    pub link_style: LinkStyle,
+    /// How symbolic links inside directories copied when unfolding were
+    /// handled, see
+    /// [`Unfolder::symlink_policy`](crate::Unfolder::symlink_policy).
+    pub symlink_policy: SymlinkPolicy,
+}
+
+/// Read the mark left on `path` when it was unfolded with
+/// [`Unfolder::mark`](crate::Unfolder::mark).
+///
+/// Returns `None` if `path` is not marked.
+pub fn read_mark<P: AsRef<Path>>(path: P) -> Result<Option<Mark>> {
+    let path = path.as_ref();
+    if path.is_symlink() {
+        return Ok(None);
+    }
+    path.symlink_metadata()
+        .context(format!("{:#?} is unreachable.", path))?;
+    try_read_mark(path).context(format!("Could not read mark of {:#?}.", path))
+}
+
+/// Replace the marked file or directory at `path` with the symbolic link it
+/// was unfolded from, returning its mark.
+///
+/// Any changes made to `path` since it was unfolded are lost. The symbolic
+/// link is built at a temporary path next to `path`, and only swapped with it
+/// once complete, so `path` is left untouched if an error occurs.
+pub fn refold<P: AsRef<Path>>(path: P) -> Result<Mark> {
+    let path = path.as_ref();
+    let Some(mark) = read_mark(path)? else {
+        bail!("{:#?} is not marked as unfolded.", path);
+    };
+    try_relink(path, &mark.link_target).context(format!("Could not refold {:#?}.", path))?;
+    Ok(mark)
+}
+
+/// Whether the extended attribute `name` is part of a mark, which should not
+/// be copied along with other extended attributes.
+#[cfg(unix)]
+pub(crate) fn is_mark(name: &std::ffi::OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(PREFIX.as_bytes())
+}
+
+/// Mark `path` with where it came from.
+#[cfg(unix)]
+pub(crate) fn try_mark(path: &Path, mark: &Mark) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    // extended attributes can only be set on writable files, and copies of
+    // read-only files are read-only themselves.
+    let permissions = path.metadata()?.permissions();
+    let read_only = permissions.mode() & 0o200 == 0;
+    if read_only {
+        let writable = std::fs::Permissions::from_mode(permissions.mode() | 0o200);
+        std::fs::set_permissions(path, writable)?;
+    }
+    let kind = serde_json::to_value(mark.kind)?;
+    let dir_depth = mark.dir_depth.to_string();
+    let link_style = serde_json::to_value(mark.link_style)?;
+    let symlink_policy = serde_json::to_value(mark.symlink_policy)?;
+    let result = [
+        (LINK_TARGET, mark.link_target.as_os_str().as_bytes()),
+        (TARGET, mark.target.as_os_str().as_bytes()),
+        (KIND, kind.as_str().unwrap_or_default().as_bytes()),
+        (FINGERPRINT, mark.fingerprint.as_bytes()),
+        (SOURCE_FINGERPRINT, mark.source_fingerprint.as_bytes()),
+        (DIR_DEPTH, dir_depth.as_bytes()),
+        (
+            LINK_STYLE,
+            link_style.as_str().unwrap_or_default().as_bytes(),
+        ),
+        (
+            SYMLINK_POLICY,
+            symlink_policy.as_str().unwrap_or_default().as_bytes(),
+        ),
+    ]
+    .into_iter()
+    .try_for_each(|(name, value)| xattr::set(path, name, value));
+    if read_only {
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(result?)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn try_mark(_path: &Path, _mark: &Mark) -> Result<()> {
+    bail!("Unfolded paths can only be marked on Unix.")
+}
+
+#[cfg(unix)]
+fn try_read_mark(path: &Path) -> Result<Option<Mark>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let link_target = match xattr::get(path, LINK_TARGET) {
+        // nothing can be marked on filesystems without extended attributes.
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported => return Ok(None),
+        result => result?,
+    };
+    let Some(link_target) = link_target else {
+        return Ok(None);
+    };
+    let get =
+        |name| xattr::get(path, name)?.context(format!("The attribute {:#?} is missing.", name));
+    let kind = String::from_utf8(get(KIND)?)?;
+    let dir_depth = String::from_utf8(get(DIR_DEPTH)?)?;
+    let link_style = String::from_utf8(get(LINK_STYLE)?)?;
+    let symlink_policy = String::from_utf8(get(SYMLINK_POLICY)?)?;
+    Ok(Some(Mark {
+        link_target: OsStr::from_bytes(&link_target).into(),
+        target: OsStr::from_bytes(&get(TARGET)?).into(),
+        kind: serde_json::from_value(kind.clone().into())
+            .context(format!("{:#?} is not a kind of unfold.", kind))?,
+        fingerprint: String::from_utf8(get(FINGERPRINT)?)?,
+        source_fingerprint: String::from_utf8(get(SOURCE_FINGERPRINT)?)?,
+        dir_depth: dir_depth
+            .parse()
+            .context(format!("{:#?} is not a directory depth.", dir_depth))?,
+        link_style: serde_json::from_value(link_style.clone().into())
+            .context(format!("{:#?} is not a link style.", link_style))?,
+        symlink_policy: serde_json::from_value(symlink_policy.clone().into()).context(format!(
+            "{:#?} is not a policy for symbolic links.",
+            symlink_policy
+        ))?,
+    }))
+}
+
+#[cfg(not(unix))]
+fn try_read_mark(_path: &Path) -> Result<Option<Mark>> {
+    Ok(None)
+}
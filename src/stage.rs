@@ -0,0 +1,135 @@
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use symlink::remove_symlink_auto;
+
+/// Find an unused sibling path of `path` to build its replacement at.
+///
+/// Building the replacement next to `path` keeps both on the same
+/// filesystem, so the replacement can be renamed into place.
+pub(crate) fn try_staging_path(path: &Path) -> Result<PathBuf> {
+    let mut file_name = OsString::from(".");
+    file_name.push(
+        path.file_name()
+            .context(format!("{:#?} does not have a file name.", path))?,
+    );
+    file_name.push(format!(".unfold-{}", std::process::id()));
+    let staged = path.with_file_name(file_name);
+    if staged.symlink_metadata().is_ok() {
+        bail!("Could not stage {:#?}: {:#?} already exists.", path, staged);
+    }
+    Ok(staged)
+}
+
+/// Replace `path` with `staged`, so that `path` is never missing.
+///
+/// Files and symbolic links are renamed over `path`. Directories are swapped
+/// with `path` where the platform supports it, after which whatever was at
+/// `path` is removed.
+pub(crate) fn try_swap(staged: &Path, path: &Path) -> Result<()> {
+    let is_dir = |path: &Path| path.symlink_metadata().is_ok_and(|meta| meta.is_dir());
+    if path.symlink_metadata().is_err() || !is_dir(staged) && !is_dir(path) {
+        return std::fs::rename(staged, path)
+            .context(format!("Could not move {:#?} to {:#?}.", staged, path));
+    }
+    try_exchange(staged, path).context(format!(
+        "Could not exchange {:#?} with {:#?}.",
+        staged, path
+    ))?;
+    try_remove(staged).context(format!("Could not remove {:#?}.", staged))
+}
+
+/// Remove `staged` after building or swapping it failed with `err`, adding
+/// context to `err` if `staged` could not be removed.
+pub(crate) fn discard(staged: &Path, err: anyhow::Error) -> anyhow::Error {
+    match try_remove(staged) {
+        Ok(()) => err,
+        Err(remove_err) => err.context(format!("Could not remove {:#?}: {}", staged, remove_err)),
+    }
+}
+
+/// Remove whatever exists at `path`, if anything.
+///
+/// Directories that cannot be removed because they are read-only, such as
+/// copies made with the `mode` attribute preserved, are made writable first.
+pub(crate) fn try_remove(path: &Path) -> Result<()> {
+    match path.symlink_metadata() {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+        Ok(meta) if meta.is_symlink() => Ok(remove_symlink_auto(path)?),
+        Ok(meta) if meta.is_dir() => match std::fs::remove_dir_all(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                try_make_writable(path)?;
+                Ok(std::fs::remove_dir_all(path)?)
+            }
+            result => Ok(result?),
+        },
+        Ok(_) => Ok(std::fs::remove_file(path)?),
+    }
+}
+
+/// Give the owner of `dir`, and of every directory inside it, permission to
+/// list and modify it.
+fn try_make_writable(dir: &Path) -> Result<()> {
+    let mut permissions = dir.symlink_metadata()?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o700);
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(dir, permissions)
+        .context(format!("Could not make {:#?} writable.", dir))?;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            try_make_writable(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Atomically exchange `staged` and `path`, falling back to moving `path`
+/// aside when an atomic exchange is not supported.
+fn try_exchange(staged: &Path, path: &Path) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let staged_c = std::ffi::CString::new(staged.as_os_str().as_bytes())?;
+        let path_c = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: both paths are valid NUL-terminated strings that outlive
+        // the call, and renameat2 does not retain them.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_renameat2,
+                libc::AT_FDCWD,
+                staged_c.as_ptr(),
+                libc::AT_FDCWD,
+                path_c.as_ptr(),
+                libc::RENAME_EXCHANGE,
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if !matches!(err.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) {
+            return Err(err.into());
+        }
+    }
+
+    // without an atomic exchange, path is briefly missing, but its original
+    // contents are never lost.
+    let mut aside = staged.as_os_str().to_owned();
+    aside.push(".old");
+    let aside = PathBuf::from(aside);
+    std::fs::rename(path, &aside)?;
+    if let Err(err) = std::fs::rename(staged, path) {
+        std::fs::rename(&aside, path)?;
+        return Err(err.into());
+    }
+    std::fs::rename(&aside, staged)?;
+    Ok(())
+}
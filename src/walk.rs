@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Find all symbolic links under a directory.
+///
+/// Symbolic links are not followed while searching, so only symbolic links
+/// located in actual subdirectories of `dir` are found. Symbolic links
+/// directly inside `dir` have a depth of 1, and no symbolic links deeper than
+/// `max_depth` are returned. Symbolic links are returned in lexicographic
+/// order, with the contents of each directory listed before the contents of
+/// its subdirectories.
+pub fn find_symlinks<P: AsRef<Path>>(dir: P, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+    let mut symlinks = Vec::new();
+    try_find_symlinks(dir.as_ref(), 1, max_depth, &mut symlinks)?;
+    Ok(symlinks)
+}
+
+fn try_find_symlinks(
+    dir: &Path,
+    depth: usize,
+    max_depth: Option<usize>,
+    symlinks: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if max_depth.is_some_and(|max_depth| depth > max_depth) {
+        return Ok(());
+    }
+
+    let mut children = dir
+        .read_dir()
+        .context(format!("Could not read contents of {:#?}", dir))?
+        .map(|child| child.map(|child| child.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .context(format!("Could not read contents of {:#?}", dir))?;
+    children.sort();
+
+    let mut subdirs = Vec::new();
+    for child in children {
+        if child.is_symlink() {
+            symlinks.push(child);
+        } else if child.is_dir() {
+            subdirs.push(child);
+        }
+    }
+    for subdir in subdirs {
+        try_find_symlinks(&subdir, depth + 1, max_depth, symlinks)?;
+    }
+    Ok(())
+}
@@ -1,8 +1,14 @@
 use anyhow::Result;
 use assert_cmd::Command;
 use dircpy::copy_dir;
+use std::fs::{File, FileTimes};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use tempfile::TempDir;
+use unfold_symlinks::{
+    read_mark, Attribute, Folder, Journal, LinkStyle, Pattern, Skip, SymlinkPolicy, UnfoldKind,
+    Unfolder, Unfolding,
+};
 
 const CONTENT_PATH: &str = "tests/test_resources/media";
 const PERCY_JACKSON_BOOK: &str =
@@ -28,7 +34,11 @@ struct TestEnvironment {
 
 impl TestEnvironment {
     fn new() -> TestEnvironment {
-        let working_dir = tempfile::tempdir().unwrap();
+        TestEnvironment::new_in(std::env::temp_dir())
+    }
+
+    fn new_in<P: AsRef<Path>>(dir: P) -> TestEnvironment {
+        let working_dir = tempfile::tempdir_in(dir).unwrap();
         if let Err(err) = copy_dir(CONTENT_PATH, working_dir.path().join("media")) {
             eprintln!("{}", err);
         }
@@ -73,6 +83,30 @@ impl TestEnvironment {
         self.get_full_path(local_path).is_dir()
     }
 
+    fn set_modified<P: AsRef<Path>>(&self, local_path: P, modified: SystemTime) -> Result<()> {
+        let times = FileTimes::new()
+            .set_accessed(modified)
+            .set_modified(modified);
+        File::open(self.get_full_path(local_path))?.set_times(times)?;
+        Ok(())
+    }
+
+    fn modified<P: AsRef<Path>>(&self, local_path: P) -> Result<SystemTime> {
+        let modified = self.get_full_path(local_path).metadata()?.modified()?;
+        Ok(modified)
+    }
+
+    fn staged_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut staged_paths = Vec::new();
+        for child in self.root().read_dir()? {
+            let child = child?.path();
+            if child.to_string_lossy().contains(".unfold-") {
+                staged_paths.push(child);
+            }
+        }
+        Ok(staged_paths)
+    }
+
     fn read_to_string<P: AsRef<Path>>(&self, local_path: P) -> Result<String> {
         let contents = std::fs::read_to_string(self.get_full_path(local_path))?;
         Ok(contents)
@@ -84,7 +118,7 @@ fn test_help_output() -> Result<()> {
     let mut cmd = Command::cargo_bin("unfold")?;
     for arg in ["-h", "--help"] {
         for text in [
-            "Usage: unfold [OPTIONS] <SYMLINK>...",
+            "Usage: unfold [OPTIONS] [SYMLINK]...",
             "Arguments:",
             "Options:",
             "-f, --follow-to-source",
@@ -562,3 +596,1987 @@ fn verbose_output() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn library_unfold_file() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let unfolded = Unfolder::new()
+        .unfold(test_env.get_full_path(symlink))?
+        .unfolded()
+        .unwrap();
+
+    assert_eq!(unfolded.kind, UnfoldKind::File);
+    assert_eq!(unfolded.symlink, test_env.get_full_path(symlink));
+    assert_eq!(unfolded.target, test_env.get_full_path(MARTIAN_BOOK));
+    assert_eq!(
+        unfolded.bytes,
+        test_env.get_full_path(MARTIAN_BOOK).metadata()?.len(),
+    );
+    assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+    assert_eq!(
+        test_env.read_to_string(symlink)?,
+        test_env.read_to_string(MARTIAN_BOOK)?,
+    );
+    Ok(())
+}
+
+#[test]
+fn library_unfold_dir_follow_to_source() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_dir("symlink_dir1", "media/movies 📽")?;
+    test_env.create_symlink_dir("symlink_dir2", "symlink_dir1")?;
+
+    let unfolded = Unfolder::new()
+        .follow_to_source(true)
+        .unfold(test_env.get_full_path("symlink_dir2"))?
+        .unfolded()
+        .unwrap();
+
+    assert_eq!(unfolded.kind, UnfoldKind::Dir);
+    assert_eq!(
+        unfolded.target,
+        test_env.get_full_path("media/movies 📽").canonicalize()?,
+    );
+    assert!(test_env.is_dir("symlink_dir2") & !test_env.is_symlink("symlink_dir2"));
+    Ok(())
+}
+
+#[test]
+fn library_num_layers_0() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let unfolded = Unfolder::new()
+        .num_layers(0)
+        .unfold(test_env.get_full_path(symlink))?;
+
+    assert_eq!(unfolded, Unfolding::Skipped(Skip::NoLayers));
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn recursive() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_file("media/books 📖/martian_link", MARTIAN_BOOK)?;
+    test_env.create_symlink_file("media/books 📖/fiction/matrix_link", MATRIX_MOVIE)?;
+    test_env.create_symlink_dir("media/books 📖/non-fiction/movies_link", "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("-r")
+        .arg("media/books 📖")
+        .assert()
+        .success();
+
+    for (symlink, target) in [
+        ("media/books 📖/martian_link", MARTIAN_BOOK),
+        ("media/books 📖/fiction/matrix_link", MATRIX_MOVIE),
+    ] {
+        assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+        assert_eq!(
+            test_env.read_to_string(symlink)?,
+            test_env.read_to_string(target)?,
+        );
+    }
+
+    // symlinks created by unfolding a directory are not unfolded.
+    let symlink_dir = "media/books 📖/non-fiction/movies_link";
+    assert!(test_env.is_dir(symlink_dir) & !test_env.is_symlink(symlink_dir));
+    for child in test_env.get_full_path(symlink_dir).read_dir()? {
+        assert!(child?.path().is_symlink());
+    }
+    Ok(())
+}
+
+#[test]
+fn recursive_max_depth() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_file("media/books 📖/martian_link", MARTIAN_BOOK)?;
+    test_env.create_symlink_file("media/books 📖/fiction/matrix_link", MATRIX_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["-r", "--max-depth", "1"])
+        .arg("media/books 📖")
+        .assert()
+        .success();
+
+    assert!(!test_env.is_symlink("media/books 📖/martian_link"));
+    assert!(test_env.is_symlink("media/books 📖/fiction/matrix_link"));
+    Ok(())
+}
+
+#[test]
+fn max_depth_requires_recursive() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--max-depth", "1"])
+        .arg(symlink)
+        .assert()
+        .failure();
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn deep() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir("media/books 📖/movies", "media/movies 📽")?;
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--deep")
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_dir(symlink) & !test_env.is_symlink(symlink));
+    for (copy, original) in [
+        ("symlink_dir/fiction/The Martian", MARTIAN_BOOK),
+        ("symlink_dir/non-fiction/1984", GEORGE_ORWELL_BOOK),
+    ] {
+        assert!(test_env.is_file(copy) & !test_env.is_symlink(copy));
+        assert_eq!(
+            test_env.read_to_string(copy)?,
+            test_env.read_to_string(original)?,
+        );
+    }
+    assert!(test_env.is_dir("symlink_dir/fiction") & !test_env.is_symlink("symlink_dir/fiction"));
+    // inner symlinks are copied by default.
+    assert!(test_env.is_symlink("symlink_dir/movies"));
+    assert_eq!(
+        test_env.get_full_path("symlink_dir/movies").read_link()?,
+        test_env.get_full_path("media/movies 📽"),
+    );
+    Ok(())
+}
+
+#[test]
+fn deep_dir_containing_symlink() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let books = test_env.get_full_path("media/books 📖");
+    symlink::symlink_dir(".", books.join("self"))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(&books)
+        .args(["--deep", "self"])
+        .assert()
+        .success();
+
+    let mut children = std::fs::read_dir(books.join("self"))?
+        .map(|child| Ok(child?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    children.sort();
+    assert_eq!(children, ["fiction", "non-fiction", "self"]);
+    assert!(test_env.is_dir("media/books 📖/self/fiction"));
+    assert!(!test_env.is_symlink("media/books 📖/self/fiction"));
+    assert_eq!(books.join("self/self").read_link()?, Path::new("."));
+    Ok(())
+}
+
+#[test]
+fn deep_links_relative() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let books = test_env.get_full_path("media/books 📖");
+    symlink::symlink_dir("fiction", books.join("stories"))?;
+    symlink::symlink_file("../fiction/The Martian", books.join("non-fiction/mars"))?;
+    symlink::symlink_dir("../movies 📽", books.join("movies"))?;
+    symlink::symlink_dir("../books 📖/fiction", books.join("novels"))?;
+    std::fs::create_dir(test_env.get_full_path("vendor"))?;
+    test_env.create_symlink_dir("vendor/books", "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "vendor/books"])
+        .assert()
+        .success();
+
+    // targets inside the copy are kept or lead to the copy, and targets
+    // outside of it still lead to the same place.
+    let copy = test_env.get_full_path("vendor/books");
+    for (symlink, link_target) in [
+        ("stories", "fiction"),
+        ("non-fiction/mars", "../fiction/The Martian"),
+        ("movies", "../../media/movies 📽"),
+        ("novels", "fiction"),
+    ] {
+        assert_eq!(copy.join(symlink).read_link()?, Path::new(link_target));
+    }
+    assert_eq!(
+        copy.join("movies").canonicalize()?,
+        test_env.get_full_path("media/movies 📽").canonicalize()?
+    );
+    Ok(())
+}
+
+#[test]
+fn deep_links_follow() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir("media/books 📖/movies", "media/movies 📽")?;
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--deep-links", "follow"])
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_dir("symlink_dir/movies") & !test_env.is_symlink("symlink_dir/movies"));
+    let copy = "symlink_dir/movies/The Matrix";
+    assert!(test_env.is_file(copy) & !test_env.is_symlink(copy));
+    assert_eq!(
+        test_env.read_to_string(copy)?,
+        test_env.read_to_string(MATRIX_MOVIE)?,
+    );
+    Ok(())
+}
+
+#[test]
+fn deep_links_skip() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir("media/books 📖/movies", "media/movies 📽")?;
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--deep-links", "skip"])
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_dir("symlink_dir/fiction"));
+    assert!(!test_env.get_full_path("symlink_dir/movies").exists());
+    assert!(!test_env.is_symlink("symlink_dir/movies"));
+    Ok(())
+}
+
+#[test]
+fn deep_links_follow_loop_reverts() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir("media/books 📖/fiction/books", "media/books 📖")?;
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--deep-links", "follow"])
+        .arg(symlink)
+        .assert()
+        .failure();
+
+    assert!(test_env.is_symlink(symlink));
+    assert_eq!(
+        test_env.get_full_path(symlink).read_link()?,
+        test_env.get_full_path("media/books 📖"),
+    );
+    Ok(())
+}
+
+#[test]
+fn dry_run() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = "symlink_file";
+    let symlink_dir = "symlink_dir";
+    test_env.create_symlink_file(symlink_file, WALL_E_MOVIE)?;
+    test_env.create_symlink_dir(symlink_dir, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--dry-run")
+        .args([symlink_file, symlink_dir])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(WALL_E_MOVIE))
+        .stdout(predicates::str::contains("(file copy, 24 bytes)"))
+        .stdout(predicates::str::contains("(directory population, 0 bytes)"));
+
+    assert!(test_env.is_symlink(symlink_file));
+    assert!(test_env.is_symlink(symlink_dir));
+    Ok(())
+}
+
+#[test]
+fn dry_run_deep() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir(symlink, "media/movies 📽")?;
+
+    // "The Matrix" is 41 bytes and "WALL·E" is 24 bytes.
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--dry-run", "--deep"])
+        .arg(symlink)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(directory copy, 65 bytes)"));
+
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn dry_run_failure() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--dry-run")
+        .args([symlink, "does_not_exist"])
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains(WALL_E_MOVIE))
+        .stderr(predicates::str::contains("does_not_exist"));
+
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn atomic_revert() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = PathBuf::from("symlink_file");
+    let symlink_dir = PathBuf::from("symlink_dir");
+    let symlink_relative = PathBuf::from("media/symlink_relative");
+    test_env.create_symlink_file(&symlink_file, MARTIAN_BOOK)?;
+    test_env.create_symlink_dir(&symlink_dir, "media/movies 📽")?;
+    symlink::symlink_file("movies 📽/WALL·E", test_env.get_full_path(&symlink_relative))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--atomic")
+        .args([
+            &symlink_file,
+            &symlink_dir,
+            &symlink_relative,
+            &PathBuf::from("does_not_exist"),
+        ])
+        .assert()
+        .failure();
+
+    assert!(test_env.is_symlink(&symlink_file));
+    assert_eq!(
+        test_env.get_full_path(&symlink_file).read_link()?,
+        test_env.get_full_path(MARTIAN_BOOK),
+    );
+    assert!(test_env.is_symlink(&symlink_dir));
+    assert_eq!(
+        test_env.get_full_path(&symlink_dir).read_link()?,
+        test_env.get_full_path("media/movies 📽"),
+    );
+    // relative symlinks are restored exactly.
+    assert!(test_env.is_symlink(&symlink_relative));
+    assert_eq!(
+        test_env.get_full_path(&symlink_relative).read_link()?,
+        PathBuf::from("movies 📽/WALL·E"),
+    );
+    Ok(())
+}
+
+#[test]
+fn atomic_revert_nested() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = PathBuf::from("symlink_dir");
+    test_env.create_symlink_dir(&symlink, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--atomic")
+        .args([
+            &symlink,
+            &symlink.join("The Matrix"),
+            &PathBuf::from("does_not_exist"),
+        ])
+        .assert()
+        .failure();
+
+    assert!(test_env.is_symlink(&symlink));
+    assert_eq!(
+        test_env.get_full_path(&symlink).read_link()?,
+        test_env.get_full_path("media/movies 📽"),
+    );
+    assert!(!test_env.is_symlink(MATRIX_MOVIE));
+    assert_eq!(
+        test_env.read_to_string(MATRIX_MOVIE)?,
+        std::fs::read_to_string(PathBuf::from(CONTENT_PATH).join("movies 📽/The Matrix"))?,
+    );
+    Ok(())
+}
+
+#[test]
+fn keep_going() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = PathBuf::from("symlink_file");
+    let symlink_dir = PathBuf::from("symlink_dir");
+    test_env.create_symlink_file(&symlink_file, MARTIAN_BOOK)?;
+    test_env.create_symlink_dir(&symlink_dir, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--keep-going")
+        .args([
+            &symlink_file,
+            &PathBuf::from("does_not_exist"),
+            &symlink_dir,
+        ])
+        .assert()
+        .code(1)
+        .stdout(predicates::str::contains(
+            "Unfolded 2 of 3 symbolic link(s).",
+        ))
+        .stdout(predicates::str::contains("does_not_exist"));
+
+    assert!(test_env.is_file(&symlink_file) & !test_env.is_symlink(&symlink_file));
+    assert!(test_env.is_dir(&symlink_dir) & !test_env.is_symlink(&symlink_dir));
+    Ok(())
+}
+
+#[test]
+fn keep_going_success() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("-k")
+        .arg(symlink)
+        .assert()
+        .code(0)
+        .stdout(predicates::str::contains(
+            "Unfolded 1 of 1 symbolic link(s).",
+        ));
+
+    assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn keep_going_and_atomic_conflict() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--keep-going", "--atomic"])
+        .arg(symlink)
+        .assert()
+        .code(2);
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn version_output() -> Result<()> {
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.arg("-V")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(env!("CARGO_PKG_VERSION")));
+    Ok(())
+}
+
+#[test]
+fn preserve_timestamps() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    test_env.set_modified(MARTIAN_BOOK, modified)?;
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("-p")
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+    assert_eq!(test_env.modified(symlink)?, modified);
+    Ok(())
+}
+
+#[test]
+fn preserve_deep() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    test_env.set_modified(GEORGE_ORWELL_BOOK, modified)?;
+    test_env.set_modified("media/books 📖/non-fiction", modified)?;
+    test_env.set_modified("media/books 📖", modified)?;
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--preserve=mode,timestamps"])
+        .arg(symlink)
+        .assert()
+        .success();
+
+    for local_path in [
+        "symlink_dir",
+        "symlink_dir/non-fiction",
+        "symlink_dir/non-fiction/1984",
+    ] {
+        assert!(!test_env.is_symlink(local_path));
+        assert_eq!(test_env.modified(local_path)?, modified);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn preserve_mode_dir() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    let target = test_env.get_full_path("media/movies 📽");
+    std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o750))?;
+    test_env.create_symlink_dir(symlink, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--preserve=mode")
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_dir(symlink) & !test_env.is_symlink(symlink));
+    let mode = test_env
+        .get_full_path(symlink)
+        .metadata()?
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o750);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn preserve_xattr() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    xattr::set(
+        test_env.get_full_path(WALL_E_MOVIE),
+        "user.rating",
+        b"five stars",
+    )?;
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--preserve=xattr")
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+    assert_eq!(
+        xattr::get(test_env.get_full_path(symlink), "user.rating")?,
+        Some(b"five stars".to_vec()),
+    );
+    Ok(())
+}
+
+#[test]
+fn reflink() -> Result<()> {
+    for when in ["auto", "never"] {
+        let test_env = TestEnvironment::new();
+        let symlink = "symlink_file";
+        test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+        let mut cmd = Command::cargo_bin("unfold")?;
+        cmd.current_dir(test_env.root())
+            .arg(format!("--reflink={}", when))
+            .arg(symlink)
+            .assert()
+            .success();
+
+        assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+        assert_eq!(
+            test_env.read_to_string(symlink)?,
+            test_env.read_to_string(MARTIAN_BOOK)?
+        );
+    }
+    Ok(())
+}
+
+/// Whether `dir` exists and is on a tmpfs.
+#[cfg(target_os = "linux")]
+fn is_tmpfs(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(dir) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    unsafe {
+        libc::statfs(dir.as_ptr(), stat.as_mut_ptr()) == 0
+            && stat.assume_init().f_type == libc::TMPFS_MAGIC
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reflink_always() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    // tmpfs does not support reflinks, so cloning is only sure to fail there.
+    let shm = Path::new("/dev/shm");
+    if !is_tmpfs(shm) {
+        eprintln!("Skipping, since {:#?} is not a tmpfs.", shm);
+        return Ok(());
+    }
+    let test_env = TestEnvironment::new_in(shm);
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--reflink=always", symlink])
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains(format!(
+            "Could not clone file {:#?}",
+            test_env.get_full_path(MARTIAN_BOOK)
+        )));
+    assert!(test_env.is_symlink(symlink));
+    assert!(test_env.staged_paths()?.is_empty());
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--reflink=never", symlink])
+        .assert()
+        .success();
+    let copy = test_env.get_full_path(symlink).symlink_metadata()?;
+    let target = test_env.get_full_path(MARTIAN_BOOK).metadata()?;
+    assert!(copy.is_file());
+    assert_eq!(copy.nlink(), 1);
+    assert_ne!(copy.ino(), target.ino());
+    std::fs::write(test_env.get_full_path(symlink), "fan fiction")?;
+    assert_ne!(test_env.read_to_string(MARTIAN_BOOK)?, "fan fiction");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn hardlink() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--hardlink")
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+    let link = test_env.get_full_path(symlink).metadata()?;
+    let target = test_env.get_full_path(MARTIAN_BOOK).metadata()?;
+    assert_eq!(link.ino(), target.ino());
+    assert_eq!(target.nlink(), 2);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn special_fifo() -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let test_env = TestEnvironment::new();
+    let fifo = test_env.get_full_path("media/pipe");
+    let fifo_c = std::ffi::CString::new(fifo.to_str().unwrap())?;
+    assert_eq!(unsafe { libc::mkfifo(fifo_c.as_ptr(), 0o644) }, 0);
+    test_env.create_symlink_file("symlink_fifo", "media/pipe")?;
+    test_env.create_symlink_dir("symlink_deep_dir", "media")?;
+    test_env.create_symlink_dir("skipped_deep_dir", "media")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--skip-special", "-v", "symlink_fifo"])
+        .assert()
+        .success()
+        .stdout("Skipped \"symlink_fifo\", which targets a special file\n");
+    assert!(test_env.is_symlink("symlink_fifo"));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "symlink_fifo", "symlink_deep_dir"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--skip-special", "skipped_deep_dir"])
+        .assert()
+        .success();
+
+    for path in ["symlink_fifo", "symlink_deep_dir/pipe"] {
+        let file_type = test_env.get_full_path(path).symlink_metadata()?.file_type();
+        assert!(file_type.is_fifo());
+    }
+    assert!(!test_env.get_full_path("skipped_deep_dir/pipe").exists());
+    assert!(test_env.is_dir("skipped_deep_dir/books 📖"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn special_socket_in_deep_dir() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let _listener = std::os::unix::net::UnixListener::bind(test_env.get_full_path("media/sock"))?;
+    test_env.create_symlink_dir("symlink_deep_dir", "media")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "symlink_deep_dir"])
+        .assert()
+        .success()
+        .stderr(format!(
+            "Warning: Skipped {:#?}, which is a socket\n",
+            test_env.get_full_path("media/sock")
+        ));
+    assert!(test_env.is_dir("symlink_deep_dir"));
+    assert!(test_env.is_dir("symlink_deep_dir/books 📖"));
+    assert!(!test_env.get_full_path("symlink_deep_dir/sock").exists());
+    Ok(())
+}
+
+#[test]
+fn hardlink_dry_run() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = "symlink_file";
+    let symlink_dir = "symlink_dir";
+    test_env.create_symlink_file(symlink_file, WALL_E_MOVIE)?;
+    test_env.create_symlink_dir(symlink_dir, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--hardlink", "--dry-run", symlink_file, symlink_dir])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(hard link, 0 bytes)"))
+        .stdout(predicates::str::contains("(directory population, 0 bytes)"));
+
+    assert!(test_env.is_symlink(symlink_file));
+    Ok(())
+}
+
+#[test]
+fn fold() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let file = "The Martian (copy)";
+    std::fs::copy(
+        test_env.get_full_path(MARTIAN_BOOK),
+        test_env.get_full_path(file),
+    )?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["fold", file, MARTIAN_BOOK])
+        .assert()
+        .success();
+
+    assert!(test_env.is_symlink(file));
+    assert_eq!(
+        test_env.get_full_path(file).read_link()?,
+        test_env.root().canonicalize()?.join(MARTIAN_BOOK)
+    );
+    Ok(())
+}
+
+#[test]
+fn fold_relative() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let file = "media/movies 📽/The Martian";
+    std::fs::copy(
+        test_env.get_full_path(MARTIAN_BOOK),
+        test_env.get_full_path(file),
+    )?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["fold", "--relative", file, MARTIAN_BOOK])
+        .assert()
+        .success();
+
+    assert!(test_env.is_symlink(file));
+    assert_eq!(
+        test_env.get_full_path(file).read_link()?,
+        PathBuf::from("../books 📖/fiction/The Martian")
+    );
+    assert_eq!(
+        test_env.read_to_string(file)?,
+        test_env.read_to_string(MARTIAN_BOOK)?
+    );
+    Ok(())
+}
+
+#[test]
+fn fold_different_contents() -> Result<()> {
+    let test_env = TestEnvironment::new();
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["fold", WALL_E_MOVIE, MATRIX_MOVIE])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("its contents differ from"));
+
+    assert!(test_env.is_file(WALL_E_MOVIE) & !test_env.is_symlink(WALL_E_MOVIE));
+    assert!(test_env.staged_paths()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn fold_after_unfold() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let unfolded = Unfolder::new().unfold(test_env.get_full_path(symlink))?;
+    assert!(unfolded.unfolded().is_some());
+    assert!(!test_env.is_symlink(symlink));
+
+    let folded = Folder::new().relative(true).fold(
+        test_env.get_full_path(symlink),
+        test_env.get_full_path(MARTIAN_BOOK),
+    )?;
+    assert_eq!(folded.link_target, PathBuf::from(MARTIAN_BOOK));
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn link_style_relative() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir(symlink, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--link-style=relative")
+        .arg(symlink)
+        .assert()
+        .success();
+
+    assert!(test_env.is_dir(symlink) & !test_env.is_symlink(symlink));
+    for child in test_env.get_full_path(symlink).read_dir()? {
+        let child_symlink = &child?.path();
+        let child_target = Path::new("../media/movies 📽").join(child_symlink.file_name().unwrap());
+        assert_eq!(child_symlink.read_link()?, child_target);
+        assert!(child_symlink.is_file());
+    }
+    Ok(())
+}
+
+#[test]
+fn dir_depth() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_dir("symlink_dir", "media")?;
+    symlink::symlink_dir("media", test_env.get_full_path("relative_dir"))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--dir-depth", "2", "--link-style=preserve"])
+        .args(["symlink_dir", "relative_dir"])
+        .assert()
+        .success();
+
+    for symlink in ["symlink_dir", "relative_dir"] {
+        for dir in ["", "books 📖", "movies 📽"] {
+            let dir = Path::new(symlink).join(dir);
+            assert!(test_env.is_dir(&dir) & !test_env.is_symlink(&dir));
+        }
+        let fiction = Path::new(symlink).join("books 📖/fiction");
+        assert!(test_env.is_symlink(&fiction));
+        assert!(test_env.is_dir(fiction));
+    }
+    assert_eq!(
+        test_env
+            .get_full_path("relative_dir/books 📖/fiction")
+            .read_link()?,
+        Path::new("../../media/books 📖/fiction")
+    );
+    assert_eq!(
+        test_env
+            .get_full_path("symlink_dir/movies 📽/The Matrix")
+            .read_link()?,
+        test_env.get_full_path("media/movies 📽/The Matrix")
+    );
+    Ok(())
+}
+
+#[test]
+fn dir_containing_symlink() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let books = test_env.get_full_path("media/books 📖");
+    symlink::symlink_dir(".", books.join("self"))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(&books).arg("self").assert().success();
+
+    let mut children = std::fs::read_dir(books.join("self"))?
+        .map(|child| Ok(child?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    children.sort();
+    assert_eq!(children, ["fiction", "non-fiction", "self"]);
+    assert!(test_env.is_symlink("media/books 📖/self/fiction"));
+    assert!(test_env.is_symlink("media/books 📖/self/self"));
+    Ok(())
+}
+
+#[test]
+fn dir_depth_containing_symlink() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let fiction = test_env.get_full_path("media/books 📖/fiction");
+    symlink::symlink_dir("..", fiction.join("up"))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(&fiction)
+        .args(["--dir-depth", "2", "up"])
+        .assert()
+        .success();
+
+    let mut children = std::fs::read_dir(fiction.join("up/fiction"))?
+        .map(|child| Ok(child?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    children.sort();
+    assert_eq!(
+        children,
+        [
+            "Percy Jackson and the Olympians - The Lightning Thief",
+            "The Martian",
+            "up"
+        ]
+    );
+    assert!(test_env.is_dir("media/books 📖/fiction/up/non-fiction"));
+    Ok(())
+}
+
+#[test]
+fn materialize_path() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_dir("vendor", "media")?;
+    let leaf = "vendor/books 📖/fiction/The Martian";
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg(leaf)
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains("is not a symlink."));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--materialize-path", "--journal", "unfold.jsonl", leaf])
+        .assert()
+        .success();
+
+    for dir in ["vendor", "vendor/books 📖", "vendor/books 📖/fiction"] {
+        assert!(test_env.is_dir(dir) & !test_env.is_symlink(dir));
+    }
+    assert!(!test_env.is_symlink(leaf));
+    assert_eq!(
+        std::fs::read(test_env.get_full_path(leaf))?,
+        std::fs::read(test_env.get_full_path(MARTIAN_BOOK))?
+    );
+    assert!(test_env.is_symlink("vendor/movies 📽"));
+    assert!(test_env.is_symlink("vendor/books 📖/non-fiction"));
+    assert!(test_env.is_symlink(PERCY_JACKSON_BOOK.replacen("media", "vendor", 1)));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["undo", "unfold.jsonl"])
+        .assert()
+        .success();
+    assert!(test_env.is_symlink("vendor"));
+    Ok(())
+}
+
+#[test]
+fn materialize_path_outside() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    std::fs::create_dir(test_env.get_full_path("repo"))?;
+    test_env.create_symlink_dir("vendor", "media")?;
+    let leaf = "vendor/books 📖/fiction/The Martian";
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.get_full_path("repo"))
+        .args(["--materialize-path", &format!("../{}", leaf)])
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains("which is outside of"));
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--materialize-path", "--within", "repo", leaf])
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains("which is outside of"));
+    assert!(test_env.is_symlink("vendor"));
+
+    // symbolic links that the current directory is reached through are
+    // left alone.
+    test_env.create_symlink_dir("repo/vendor", "media")?;
+    test_env.create_symlink_dir("alias", "repo")?;
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.get_full_path("alias"))
+        .arg("--materialize-path")
+        .arg(test_env.get_full_path("alias").join(leaf))
+        .assert()
+        .success();
+    assert!(test_env.is_symlink("alias"));
+    assert!(!test_env.is_symlink("repo/vendor"));
+    assert!(!test_env.is_symlink(format!("repo/{}", leaf)));
+    Ok(())
+}
+
+#[test]
+fn link_style_preserve() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let relative_symlink = "media/relative_dir";
+    let absolute_symlink = "absolute_dir";
+    symlink::symlink_dir("movies 📽", test_env.get_full_path(relative_symlink))?;
+    test_env.create_symlink_dir(absolute_symlink, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--link-style=preserve", relative_symlink, absolute_symlink])
+        .assert()
+        .success();
+
+    let child = "The Matrix";
+    assert_eq!(
+        test_env
+            .get_full_path(relative_symlink)
+            .join(child)
+            .read_link()?,
+        Path::new("../movies 📽").join(child)
+    );
+    assert_eq!(
+        test_env
+            .get_full_path(absolute_symlink)
+            .join(child)
+            .read_link()?,
+        test_env.get_full_path("media/movies 📽").join(child)
+    );
+    Ok(())
+}
+
+#[test]
+fn journal_undo() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = "symlink_file";
+    let symlink_dir = "symlink_dir";
+    let journal = "journal.jsonl";
+    test_env.create_symlink_file(symlink_file, MARTIAN_BOOK)?;
+    symlink::symlink_dir("media/movies 📽", test_env.get_full_path(symlink_dir))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--journal", journal, symlink_file])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--journal", journal, "--deep", symlink_dir])
+        .assert()
+        .success();
+
+    let entries = Journal::read(test_env.get_full_path(journal))?;
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].kind, UnfoldKind::File);
+    assert_eq!(entries[1].kind, UnfoldKind::DeepDir);
+    assert!(!test_env.is_symlink(symlink_file));
+    assert!(!test_env.is_symlink(symlink_dir));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["undo", journal])
+        .assert()
+        .success();
+
+    assert_eq!(
+        test_env.get_full_path(symlink_file).read_link()?,
+        test_env.get_full_path(MARTIAN_BOOK)
+    );
+    assert_eq!(
+        test_env.get_full_path(symlink_dir).read_link()?,
+        PathBuf::from("media/movies 📽")
+    );
+
+    // undoing again does nothing, since the symbolic links are restored.
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["undo", journal])
+        .assert()
+        .success();
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn journal_undo_read_only() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    let journal = "journal.jsonl";
+    let target = test_env.get_full_path("media/books 📖");
+    let read_only = [target.join("fiction"), target.clone()];
+    for dir in &read_only {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o555))?;
+    }
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--journal", journal, "--deep", "-p", symlink])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["undo", journal])
+        .assert()
+        .success();
+
+    assert_eq!(test_env.get_full_path(symlink).read_link()?, target);
+    assert!(test_env.staged_paths()?.is_empty());
+    for dir in &read_only {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn journal_undo_changed() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    let journal = "journal.jsonl";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--journal", journal, symlink])
+        .assert()
+        .success();
+    std::fs::write(test_env.get_full_path(symlink), "edited")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["undo", journal])
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains(
+            "has changed since it was unfolded",
+        ));
+    assert_eq!(test_env.read_to_string(symlink)?, "edited");
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["undo", "--force", journal])
+        .assert()
+        .success();
+    assert!(test_env.is_symlink(symlink));
+    assert!(test_env.staged_paths()?.is_empty());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn mark_status_refold() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = "symlink_file";
+    let symlink_dir = "symlink_dir";
+    test_env.create_symlink_file(symlink_file, MARTIAN_BOOK)?;
+    symlink::symlink_dir("media/movies 📽", test_env.get_full_path(symlink_dir))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--mark", symlink_file, symlink_dir])
+        .assert()
+        .success();
+
+    assert!(!test_env.is_symlink(symlink_file));
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["status", symlink_file, symlink_dir, WALL_E_MOVIE])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(format!(
+            "\"{}\" was unfolded from a symbolic link to {:#?}",
+            symlink_file,
+            test_env.get_full_path(MARTIAN_BOOK)
+        )))
+        .stdout(predicates::str::contains(format!(
+            "\"{}\" was unfolded from a symbolic link to \"media/movies 📽\"",
+            symlink_dir
+        )))
+        .stdout(predicates::str::contains(format!(
+            "{:#?} is not marked as unfolded.",
+            WALL_E_MOVIE
+        )));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refold", symlink_file, symlink_dir])
+        .assert()
+        .success();
+
+    assert_eq!(
+        test_env.get_full_path(symlink_file).read_link()?,
+        test_env.get_full_path(MARTIAN_BOOK)
+    );
+    assert_eq!(
+        test_env.get_full_path(symlink_dir).read_link()?,
+        PathBuf::from("media/movies 📽")
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn mark_not_preserved() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    let second_symlink = "second_symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+    test_env.create_symlink_file(second_symlink, symlink)?;
+
+    Unfolder::new()
+        .mark(true)
+        .unfold(test_env.get_full_path(symlink))?;
+    Unfolder::new()
+        .preserve([Attribute::Xattr])
+        .unfold(test_env.get_full_path(second_symlink))?;
+
+    let mark = read_mark(test_env.get_full_path(symlink))?.unwrap();
+    assert_eq!(mark.link_target, test_env.get_full_path(MARTIAN_BOOK));
+    assert_eq!(read_mark(test_env.get_full_path(second_symlink))?, None);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn refresh() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink_file = "symlink_file";
+    let symlink_dir = "symlink_dir";
+    test_env.create_symlink_file(symlink_file, MARTIAN_BOOK)?;
+    test_env.create_symlink_dir(symlink_dir, "media/movies 📽")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--mark", "--deep", symlink_file, symlink_dir])
+        .assert()
+        .success();
+    std::fs::write(test_env.get_full_path(MARTIAN_BOOK), "sequel")?;
+    std::fs::write(test_env.get_full_path(WALL_E_MOVIE), "sequel")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", symlink_file, symlink_dir])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(format!(
+            "Updated {:#?}",
+            symlink_file
+        )))
+        .stdout(predicates::str::contains(format!(
+            "Updated {:#?}",
+            symlink_dir
+        )));
+
+    assert_eq!(test_env.read_to_string(symlink_file)?, "sequel");
+    let copied_movie = Path::new(symlink_dir).join("WALL·E");
+    assert_eq!(test_env.read_to_string(&copied_movie)?, "sequel");
+    assert!(!test_env.is_symlink(&copied_movie));
+
+    // nothing changed since the refresh.
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", symlink_file, symlink_dir])
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn refresh_dir_depth() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir(symlink, "media")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args([
+            "--mark",
+            "--dir-depth",
+            "2",
+            "--link-style=relative",
+            symlink,
+        ])
+        .assert()
+        .success();
+
+    // files reached through the symbolic links are not copied, so changing
+    // them does not call for a refresh.
+    std::fs::write(test_env.get_full_path(MARTIAN_BOOK), "sequel")?;
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", symlink])
+        .assert()
+        .success()
+        .stdout("");
+
+    std::fs::write(test_env.get_full_path("media/books 📖/Dune"), "spice")?;
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", symlink])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(format!("Updated {:#?}", symlink)));
+
+    assert!(test_env.is_dir("symlink_dir/books 📖") & !test_env.is_symlink("symlink_dir/books 📖"));
+    assert_eq!(
+        test_env
+            .get_full_path("symlink_dir/books 📖/Dune")
+            .read_link()?,
+        Path::new("../../media/books 📖/Dune")
+    );
+    let mark = read_mark(test_env.get_full_path(symlink))?.unwrap();
+    assert_eq!(mark.dir_depth, 2);
+    assert_eq!(mark.link_style, LinkStyle::Relative);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn refresh_deep_links_follow() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let sdk = test_env.get_full_path("sdk");
+    std::fs::create_dir_all(sdk.join("include"))?;
+    std::fs::write(sdk.join("include/main.h"), "int main();")?;
+    symlink::symlink_dir("include", sdk.join("headers"))?;
+    test_env.create_symlink_dir("vendored_sdk", "sdk")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--deep-links", "follow", "--mark", "vendored_sdk"])
+        .assert()
+        .success();
+
+    std::fs::write(sdk.join("include/main.h"), "int main(void);")?;
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", "vendored_sdk"])
+        .assert()
+        .success()
+        .stdout("Updated \"vendored_sdk\"\n");
+
+    assert!(test_env.is_dir("vendored_sdk/headers") & !test_env.is_symlink("vendored_sdk/headers"));
+    assert_eq!(
+        test_env.read_to_string("vendored_sdk/headers/main.h")?,
+        "int main(void);"
+    );
+    let mark = read_mark(test_env.get_full_path("vendored_sdk"))?.unwrap();
+    assert_eq!(mark.symlink_policy, SymlinkPolicy::Follow);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn refresh_modified() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--mark", symlink])
+        .assert()
+        .success();
+    std::fs::write(test_env.get_full_path(MARTIAN_BOOK), "sequel")?;
+    std::fs::write(test_env.get_full_path(symlink), "fan fiction")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", symlink])
+        .assert()
+        .code(1)
+        .stdout(predicates::str::contains(
+            "has local modifications that would be lost.",
+        ));
+    assert_eq!(test_env.read_to_string(symlink)?, "fan fiction");
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refresh", "--force", symlink])
+        .assert()
+        .success();
+    assert_eq!(test_env.read_to_string(symlink)?, "sequel");
+    assert!(test_env.staged_paths()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn refold_unmarked() -> Result<()> {
+    let test_env = TestEnvironment::new();
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["refold", WALL_E_MOVIE])
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains("is not marked as unfolded."));
+
+    assert!(test_env.is_file(WALL_E_MOVIE) & !test_env.is_symlink(WALL_E_MOVIE));
+    Ok(())
+}
+
+#[test]
+fn format_json() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    let second_symlink = "second_symlink_file";
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+    test_env.create_symlink_file(second_symlink, symlink)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    let output = cmd
+        .current_dir(test_env.root())
+        .args(["--format=json", "-v", "-n", "2", second_symlink])
+        .output()?;
+    assert!(output.status.success());
+
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        reports,
+        serde_json::json!([{
+            "symlink": second_symlink,
+            "chain": [test_env.get_full_path(symlink), test_env.get_full_path(WALL_E_MOVIE)],
+            "kind": "file",
+            "bytes": 24,
+            "outcome": "unfolded",
+            "error": null,
+        }])
+    );
+    Ok(())
+}
+
+#[test]
+fn format_json_failed() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    let output = cmd
+        .current_dir(test_env.root())
+        .args(["--format=json", "--within", "media/books 📖", symlink])
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(reports[0]["outcome"], "failed");
+    assert_eq!(
+        reports[0]["chain"],
+        serde_json::json!([test_env.get_full_path(WALL_E_MOVIE)])
+    );
+    assert_eq!(reports[0]["kind"], serde_json::Value::Null);
+    assert!(reports[0]["error"]
+        .as_str()
+        .is_some_and(|err| err.contains("is outside of")));
+    Ok(())
+}
+
+#[test]
+fn format_jsonl_atomic() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_file";
+    test_env.create_symlink_file(symlink, WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    let output = cmd
+        .current_dir(test_env.root())
+        .args(["--format=jsonl", "--atomic", symlink, MATRIX_MOVIE])
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+
+    let reports = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<serde_json::Result<Vec<serde_json::Value>>>()?;
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports[0]["outcome"], "unfolded");
+    assert_eq!(reports[1]["outcome"], "failed");
+    assert_eq!(reports[1]["symlink"], MATRIX_MOVIE);
+    assert!(reports[1]["error"]
+        .as_str()
+        .is_some_and(|err| err.contains("is not a symlink")));
+    assert_eq!(reports[2]["outcome"], "reverted");
+    assert_eq!(reports[2]["symlink"], symlink);
+    assert!(test_env.is_symlink(symlink));
+    Ok(())
+}
+
+#[test]
+fn symlinks_from_stdin() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlinks = ["symlink 📖", "symlink\nwith newline", "symlink_file"];
+    for symlink in symlinks {
+        test_env.create_symlink_file(symlink, MARTIAN_BOOK)?;
+    }
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["-0", "-"])
+        .write_stdin(symlinks.join("\0"))
+        .assert()
+        .success();
+
+    for symlink in symlinks {
+        assert!(test_env.is_file(symlink) & !test_env.is_symlink(symlink));
+    }
+    Ok(())
+}
+
+#[test]
+fn symlinks_from_file() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let list = "symlinks.txt";
+    test_env.create_symlink_file("symlink 📖", MARTIAN_BOOK)?;
+    test_env.create_symlink_dir("symlink_dir", "media/movies 📽")?;
+    test_env.create_symlink_file("symlink_file", WALL_E_MOVIE)?;
+    std::fs::write(test_env.get_full_path(list), "symlink 📖\n\nsymlink_dir\n")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--from-file", list, "symlink_file"])
+        .assert()
+        .success();
+
+    assert!(test_env.is_file("symlink 📖") & !test_env.is_symlink("symlink 📖"));
+    assert!(test_env.is_dir("symlink_dir") & !test_env.is_symlink("symlink_dir"));
+    assert!(test_env.is_file("symlink_file") & !test_env.is_symlink("symlink_file"));
+    Ok(())
+}
+
+#[test]
+fn include_exclude() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    std::fs::create_dir_all(test_env.get_full_path("links/.git"))?;
+    test_env.create_symlink_file("links/book.txt", MARTIAN_BOOK)?;
+    test_env.create_symlink_file("links/movie", WALL_E_MOVIE)?;
+    test_env.create_symlink_file("links/.git/book.txt", GEORGE_ORWELL_BOOK)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["-r", "-k", "links"])
+        .args(["--include", "*.txt", "--exclude", "**/.git/**"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Unfolded 1 of 3 symbolic link(s).",
+        ));
+
+    assert!(!test_env.is_symlink("links/book.txt"));
+    assert!(test_env.is_symlink("links/movie"));
+    assert!(test_env.is_symlink("links/.git/book.txt"));
+    Ok(())
+}
+
+#[test]
+fn exclude_before_validation() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    std::fs::write(test_env.get_full_path("notes.txt"), "notes")?;
+    symlink::symlink_file("loop", test_env.get_full_path("loop"))?;
+    test_env.create_symlink_file("symlink_file", MARTIAN_BOOK)?;
+
+    for path in ["notes.txt", "loop"] {
+        let mut cmd = Command::cargo_bin("unfold")?;
+        cmd.current_dir(test_env.root()).arg(path).assert().code(1);
+    }
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["-v", "--exclude", "notes.txt", "--exclude", "loop"])
+        .args(["notes.txt", "loop", "symlink_file"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Skipped \"notes.txt\", which is filtered out",
+        ))
+        .stdout(predicates::str::contains(
+            "Skipped \"loop\", which is filtered out",
+        ));
+
+    assert!(test_env.is_symlink("loop"));
+    assert!(!test_env.is_symlink("symlink_file"));
+    assert_eq!(test_env.read_to_string("notes.txt")?, "notes");
+    Ok(())
+}
+
+#[test]
+fn target_include_exclude() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_file("fiction", MARTIAN_BOOK)?;
+    test_env.create_symlink_file("non-fiction", GEORGE_ORWELL_BOOK)?;
+    test_env.create_symlink_file("movie", WALL_E_MOVIE)?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["fiction", "non-fiction", "movie"])
+        .args(["--target-include", "**/books 📖/**"])
+        .args(["--target-exclude", "**/non-fiction/*"])
+        .assert()
+        .success();
+
+    assert!(!test_env.is_symlink("fiction"));
+    assert!(test_env.is_symlink("non-fiction"));
+    assert!(test_env.is_symlink("movie"));
+    Ok(())
+}
+
+#[test]
+fn target_exclude_relative() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    std::fs::create_dir(test_env.get_full_path("links"))?;
+    symlink::symlink_file(
+        Path::new("..").join(GEORGE_ORWELL_BOOK),
+        test_env.get_full_path("links/non-fiction"),
+    )?;
+    symlink::symlink_file(
+        Path::new("..").join(MARTIAN_BOOK),
+        test_env.get_full_path("links/fiction"),
+    )?;
+
+    let root = test_env.root().canonicalize()?;
+    let excluded = format!(
+        "{}/**",
+        Pattern::escape(&root.join("media/books 📖/non-fiction").to_string_lossy())
+    );
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["links/non-fiction", "links/fiction"])
+        .args(["--target-exclude", &excluded])
+        .assert()
+        .success();
+
+    assert!(test_env.is_symlink("links/non-fiction"));
+    assert!(!test_env.is_symlink("links/fiction"));
+    Ok(())
+}
+
+#[test]
+fn symlink_loop() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    symlink::symlink_file("b", test_env.get_full_path("a"))?;
+    symlink::symlink_file("c", test_env.get_full_path("b"))?;
+    symlink::symlink_file("a", test_env.get_full_path("c"))?;
+    test_env.create_symlink_file("symlink_file", MARTIAN_BOOK)?;
+
+    let chain = ["a", "b", "c", "a"]
+        .map(|path| format!("{:#?}", test_env.root().canonicalize().unwrap().join(path)));
+    for args in [
+        vec!["-k", "a", "symlink_file"],
+        vec!["-k", "-f", "a", "symlink_file"],
+    ] {
+        let mut cmd = Command::cargo_bin("unfold")?;
+        cmd.current_dir(test_env.root())
+            .args(args)
+            .assert()
+            .code(1)
+            .stderr(predicates::str::contains(format!(
+                "leads to a symlink loop: {}",
+                chain.join(" -> ")
+            )))
+            .stdout(predicates::str::contains("Could not unfold:"));
+    }
+
+    assert!(test_env.is_symlink("a"));
+    assert!(!test_env.is_symlink("symlink_file"));
+    Ok(())
+}
+
+#[test]
+fn symlink_loop_through_dir() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    symlink::symlink_file("b/x", test_env.get_full_path("a"))?;
+    symlink::symlink_dir("a", test_env.get_full_path("b"))?;
+
+    let chain = ["a", "b/x"]
+        .map(|path| format!("{:#?}", test_env.root().canonicalize().unwrap().join(path)));
+    for args in [vec!["a"], vec!["-f", "a"], vec!["--broken=remove", "a"]] {
+        let mut cmd = Command::cargo_bin("unfold")?;
+        cmd.current_dir(test_env.root())
+            .args(args)
+            .assert()
+            .code(1)
+            .stderr(predicates::str::contains(format!(
+                "leads to a symlink loop: {}",
+                chain.join(" -> ")
+            )));
+    }
+
+    assert!(test_env.is_symlink("a"));
+    Ok(())
+}
+
+#[test]
+fn broken_policy() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_file("symlink_file", MARTIAN_BOOK)?;
+    symlink::symlink_file("missing", test_env.get_full_path("broken"))?;
+    symlink::symlink_file("broken", test_env.get_full_path("broken_chain"))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["broken_chain", "symlink_file"])
+        .assert()
+        .code(1)
+        .stderr(predicates::str::contains("is a broken symlink."));
+    assert!(test_env.is_symlink("symlink_file"));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--broken=skip", "broken", "broken_chain", "symlink_file"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Warning: Skipped \"broken_chain\", which is a broken symlink",
+        ));
+    assert!(!test_env.is_symlink("symlink_file"));
+    assert!(test_env.is_symlink("broken"));
+    assert!(test_env.is_symlink("broken_chain"));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["-v", "--broken=keep", "broken_chain"])
+        .assert()
+        .success()
+        .stdout("Kept \"broken_chain\", which is a broken symlink\n")
+        .stderr("");
+    assert!(test_env.is_symlink("broken_chain"));
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args([
+            "--broken=remove",
+            "--exclude",
+            "broken",
+            "broken",
+            "broken_chain",
+        ])
+        .assert()
+        .success();
+    assert!(test_env.is_symlink("broken"));
+    assert!(test_env
+        .get_full_path("broken_chain")
+        .symlink_metadata()
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn within() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let repo = test_env.get_full_path("repo");
+    std::fs::create_dir_all(repo.join("dir"))?;
+    std::fs::create_dir_all(repo.join("leaky_dir"))?;
+    std::fs::write(repo.join("dir/ok"), "ok")?;
+    symlink::symlink_file("dir/ok", repo.join("symlink_file"))?;
+    symlink::symlink_dir("dir", repo.join("symlink_dir"))?;
+    symlink::symlink_file(format!("../{}", MARTIAN_BOOK), repo.join("escape"))?;
+    symlink::symlink_file("escape", repo.join("symlink_escape"))?;
+    symlink::symlink_file("../../media", repo.join("leaky_dir/leak"))?;
+    symlink::symlink_dir("leaky_dir", repo.join("symlink_leaky_dir"))?;
+
+    let root = format!("{:#?}", repo.canonicalize()?);
+    for args in [
+        vec!["repo/escape"],
+        vec!["-f", "repo/symlink_escape"],
+        vec!["repo/symlink_leaky_dir"],
+    ] {
+        let mut cmd = Command::cargo_bin("unfold")?;
+        cmd.current_dir(test_env.root())
+            .args(["--within", "repo"])
+            .args(&args)
+            .assert()
+            .code(1)
+            .stderr(predicates::str::contains(format!(
+                "which is outside of {}.",
+                root
+            )));
+        assert!(test_env.is_symlink(args.last().unwrap()));
+    }
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--within", "repo", "repo/symlink_file", "repo/symlink_dir"])
+        .assert()
+        .success();
+    assert!(!test_env.is_symlink("repo/symlink_file"));
+    assert!(test_env.is_dir("repo/symlink_dir") & !test_env.is_symlink("repo/symlink_dir"));
+    Ok(())
+}
+
+#[test]
+fn within_dry_run() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let repo = test_env.get_full_path("repo");
+    std::fs::create_dir_all(repo.join("leaky_dir/inner"))?;
+    symlink::symlink_file("../../../media", repo.join("leaky_dir/inner/leak"))?;
+    symlink::symlink_dir("leaky_dir", repo.join("symlink_leaky_dir"))?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--within", "repo", "--dry-run", "repo/symlink_leaky_dir"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Would unfold"));
+
+    let root = format!("{:#?}", repo.canonicalize()?);
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--within", "repo", "--dry-run", "--dir-depth", "2"])
+        .arg("repo/symlink_leaky_dir")
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicates::str::contains(format!(
+            "which is outside of {}.",
+            root
+        )));
+    assert!(test_env.is_symlink("repo/symlink_leaky_dir"));
+    Ok(())
+}
+
+#[test]
+fn explain() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_file("symlink_file", MARTIAN_BOOK)?;
+    symlink::symlink_file("symlink_file", test_env.get_full_path("symlink_symlink"))?;
+    symlink::symlink_file("missing", test_env.get_full_path("broken"))?;
+
+    let root = test_env.root().canonicalize()?;
+    let hops = format!(
+        "  1. \"symlink_file\" -> {:#?} (symlink)\n  2. {:#?} -> {:#?} (file)\n",
+        root.join("symlink_file"),
+        test_env.get_full_path(MARTIAN_BOOK),
+        test_env.get_full_path(MARTIAN_BOOK)
+    );
+    for (args, stop) in [
+        (vec![], "hop 1"),
+        (vec!["-n", "5"], "hop 2"),
+        (vec!["-f"], "hop 2"),
+    ] {
+        let mut cmd = Command::cargo_bin("unfold")?;
+        cmd.current_dir(test_env.root())
+            .arg("explain")
+            .args(args)
+            .arg("symlink_symlink")
+            .assert()
+            .success()
+            .stdout(format!(
+                "{:#?}\n{}  Unfolding would stop at {}.\n",
+                root.join("symlink_symlink"),
+                hops,
+                stop
+            ));
+    }
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["explain", "broken", MARTIAN_BOOK])
+        .assert()
+        .code(1)
+        .stdout(predicates::str::contains(
+            "(missing)\n  The chain is broken, so it cannot be unfolded.\n",
+        ))
+        .stderr(predicates::str::contains("is not a symlink."));
+
+    assert!(test_env.is_symlink("symlink_symlink"));
+    Ok(())
+}
+
+#[test]
+fn no_staged_paths_left_behind() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    test_env.create_symlink_file("symlink_file", MARTIAN_BOOK)?;
+    test_env.create_symlink_dir("symlink_dir", "media/movies 📽")?;
+    test_env.create_symlink_dir("symlink_deep_dir", "media/books 📖")?;
+    test_env.create_symlink_file("symlink_symlink", "symlink_file")?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["symlink_symlink", "symlink_file", "symlink_dir"])
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .arg("--deep")
+        .arg("symlink_deep_dir")
+        .assert()
+        .success();
+
+    assert!(test_env.is_symlink("symlink_symlink"));
+    assert!(test_env.is_file("symlink_file") & !test_env.is_symlink("symlink_file"));
+    assert!(test_env.is_dir("symlink_dir") & !test_env.is_symlink("symlink_dir"));
+    assert!(test_env.is_dir("symlink_deep_dir") & !test_env.is_symlink("symlink_deep_dir"));
+    assert!(test_env.staged_paths()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn failed_unfold_leaves_symlink_in_place() -> Result<()> {
+    let test_env = TestEnvironment::new();
+    let symlink = "symlink_dir";
+    test_env.create_symlink_dir("media/books 📖/fiction/books", "media/books 📖")?;
+    test_env.create_symlink_dir(symlink, "media/books 📖")?;
+    let modified = test_env
+        .get_full_path(symlink)
+        .symlink_metadata()?
+        .modified()?;
+
+    let mut cmd = Command::cargo_bin("unfold")?;
+    cmd.current_dir(test_env.root())
+        .args(["--deep", "--deep-links", "follow"])
+        .arg(symlink)
+        .assert()
+        .failure();
+
+    // the symlink was never removed, rather than removed and recreated.
+    assert!(test_env.is_symlink(symlink));
+    assert_eq!(
+        test_env
+            .get_full_path(symlink)
+            .symlink_metadata()?
+            .modified()?,
+        modified,
+    );
+    assert!(test_env.staged_paths()?.is_empty());
+    Ok(())
+}